@@ -0,0 +1,27 @@
+CREATE VIRTUAL TABLE screenshots_fts USING fts5(
+    text_content,
+    description,
+    window_title,
+    application_name,
+    content = 'screenshots',
+    content_rowid = 'rowid'
+);
+
+INSERT INTO screenshots_fts (screenshots_fts) VALUES ('rebuild');
+
+CREATE TRIGGER screenshots_fts_insert AFTER INSERT ON screenshots BEGIN
+    INSERT INTO screenshots_fts (rowid, text_content, description, window_title, application_name)
+    VALUES (new.rowid, new.text_content, new.description, new.window_title, new.application_name);
+END;
+
+CREATE TRIGGER screenshots_fts_delete AFTER DELETE ON screenshots BEGIN
+    INSERT INTO screenshots_fts (screenshots_fts, rowid, text_content, description, window_title, application_name)
+    VALUES ('delete', old.rowid, old.text_content, old.description, old.window_title, old.application_name);
+END;
+
+CREATE TRIGGER screenshots_fts_update AFTER UPDATE OF text_content, description, window_title, application_name ON screenshots BEGIN
+    INSERT INTO screenshots_fts (screenshots_fts, rowid, text_content, description, window_title, application_name)
+    VALUES ('delete', old.rowid, old.text_content, old.description, old.window_title, old.application_name);
+    INSERT INTO screenshots_fts (rowid, text_content, description, window_title, application_name)
+    VALUES (new.rowid, new.text_content, new.description, new.window_title, new.application_name);
+END;
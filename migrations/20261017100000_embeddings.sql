@@ -0,0 +1,9 @@
+CREATE TABLE embeddings (
+    "screenshot_id" INTEGER PRIMARY KEY NOT NULL,
+    "model" VARCHAR NOT NULL,
+    "embedding" BLOB NOT NULL
+);
+
+CREATE TRIGGER embeddings_delete AFTER DELETE ON screenshots BEGIN
+    DELETE FROM embeddings WHERE screenshot_id = old.rowid;
+END;
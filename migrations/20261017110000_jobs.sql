@@ -0,0 +1,15 @@
+CREATE TABLE jobs (
+    "screenshot_id" INTEGER NOT NULL,
+    "processing_type" VARCHAR NOT NULL,
+    "status" VARCHAR NOT NULL,
+    "attempts" INTEGER NOT NULL DEFAULT 0,
+    "last_error" VARCHAR,
+    "next_attempt_at" TEXT,
+    PRIMARY KEY (screenshot_id, processing_type)
+);
+
+CREATE INDEX jobs_status ON jobs (status, next_attempt_at);
+
+CREATE TRIGGER jobs_delete AFTER DELETE ON screenshots BEGIN
+    DELETE FROM jobs WHERE screenshot_id = old.rowid;
+END;
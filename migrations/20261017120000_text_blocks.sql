@@ -0,0 +1,17 @@
+CREATE TABLE text_blocks (
+    "screenshot_id" INTEGER NOT NULL,
+    "line" INTEGER NOT NULL,
+    "word" INTEGER,
+    "text" VARCHAR NOT NULL,
+    "x" INTEGER NOT NULL,
+    "y" INTEGER NOT NULL,
+    "width" INTEGER NOT NULL,
+    "height" INTEGER NOT NULL,
+    "confidence" REAL
+);
+
+CREATE INDEX text_blocks_screenshot_id ON text_blocks (screenshot_id);
+
+CREATE TRIGGER text_blocks_delete AFTER DELETE ON screenshots BEGIN
+    DELETE FROM text_blocks WHERE screenshot_id = old.rowid;
+END;
@@ -0,0 +1,18 @@
+ALTER TABLE screenshots ADD COLUMN "summary" VARCHAR;
+ALTER TABLE screenshots ADD COLUMN "activity" VARCHAR;
+ALTER TABLE screenshots ADD COLUMN "project" VARCHAR;
+
+CREATE INDEX screenshots_activity ON screenshots (activity);
+
+CREATE TABLE screenshot_entities (
+    "screenshot_id" INTEGER NOT NULL,
+    "kind" VARCHAR NOT NULL,
+    "value" VARCHAR NOT NULL
+);
+
+CREATE INDEX screenshot_entities_screenshot_id ON screenshot_entities (screenshot_id);
+CREATE INDEX screenshot_entities_value ON screenshot_entities (kind, value);
+
+CREATE TRIGGER screenshot_entities_delete AFTER DELETE ON screenshots BEGIN
+    DELETE FROM screenshot_entities WHERE screenshot_id = old.rowid;
+END;
@@ -0,0 +1,3 @@
+ALTER TABLE screenshots ADD COLUMN image_deleted_at TEXT;
+
+CREATE INDEX screenshots_timestamp ON screenshots (timestamp);
@@ -0,0 +1,24 @@
+-- size of the encrypted image in bytes, NULL for screenshots taken before this was tracked
+ALTER TABLE screenshots ADD COLUMN image_size INTEGER;
+
+-- a single row with the total size of all image files that haven't been deleted
+CREATE TABLE storage_usage (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    image_bytes INTEGER NOT NULL
+);
+
+INSERT INTO storage_usage (id, image_bytes) VALUES (0, 0);
+
+CREATE TRIGGER storage_usage_insert AFTER INSERT ON screenshots WHEN new.image_deleted_at IS NULL BEGIN
+    UPDATE storage_usage SET image_bytes = image_bytes + COALESCE(new.image_size, 0);
+END;
+
+CREATE TRIGGER storage_usage_delete AFTER DELETE ON screenshots WHEN old.image_deleted_at IS NULL BEGIN
+    UPDATE storage_usage SET image_bytes = image_bytes - COALESCE(old.image_size, 0);
+END;
+
+CREATE TRIGGER storage_usage_update AFTER UPDATE OF image_size, image_deleted_at ON screenshots BEGIN
+    UPDATE storage_usage SET image_bytes = image_bytes
+        - CASE WHEN old.image_deleted_at IS NULL THEN COALESCE(old.image_size, 0) ELSE 0 END
+        + CASE WHEN new.image_deleted_at IS NULL THEN COALESCE(new.image_size, 0) ELSE 0 END;
+END;
@@ -0,0 +1,68 @@
+//! Active window lookup through the EWMH properties that X11 window managers maintain.
+
+use color_eyre::Result;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window};
+
+pub struct ActiveWindow {
+    pub id: Window,
+    pub app_name: String,
+    pub title: String,
+}
+
+fn intern_atom(connection: &impl Connection, name: &str) -> Result<Atom> {
+    let atom = connection
+        .intern_atom(false, name.as_bytes())?
+        .reply()?
+        .atom;
+    Ok(atom)
+}
+
+fn read_string(
+    connection: &impl Connection,
+    window: Window,
+    property: impl Into<Atom>,
+    type_: impl Into<Atom>,
+) -> Result<Vec<u8>> {
+    let reply = connection
+        .get_property(false, window, property, type_, 0, u32::MAX / 4)?
+        .reply()?;
+    Ok(reply.value)
+}
+
+/// Reads `_NET_ACTIVE_WINDOW` from the root window, along with the window's title and the
+/// class name of the application that owns it. Returns `None` if no window is focused.
+pub fn active_window(connection: &impl Connection, root: Window) -> Result<Option<ActiveWindow>> {
+    let net_active_window = intern_atom(connection, "_NET_ACTIVE_WINDOW")?;
+    let net_wm_name = intern_atom(connection, "_NET_WM_NAME")?;
+    let utf8_string = intern_atom(connection, "UTF8_STRING")?;
+
+    let reply = connection
+        .get_property(false, root, net_active_window, AtomEnum::WINDOW, 0, 1)?
+        .reply()?;
+    let window = reply.value32().and_then(|mut values| values.next());
+    let Some(window) = window.filter(|&w| w != x11rb::NONE) else {
+        return Ok(None);
+    };
+
+    let mut title = read_string(connection, window, net_wm_name, utf8_string)?;
+    if title.is_empty() {
+        // fall back to the legacy ICCCM property for windows that don't set _NET_WM_NAME
+        title = read_string(connection, window, AtomEnum::WM_NAME, AtomEnum::STRING)?;
+    }
+
+    // WM_CLASS holds two null-terminated strings, the instance name and the class name
+    let class = read_string(connection, window, AtomEnum::WM_CLASS, AtomEnum::STRING)?;
+    let app_name = class
+        .split(|&b| b == 0)
+        .filter(|s| !s.is_empty())
+        .next_back()
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .unwrap_or_default();
+
+    Ok(Some(ActiveWindow {
+        id: window,
+        app_name,
+        title: String::from_utf8_lossy(&title).into_owned(),
+    }))
+}
@@ -0,0 +1,75 @@
+use async_trait::async_trait;
+use color_eyre::Result;
+use image::RgbImage;
+use time::OffsetDateTime;
+
+#[cfg(target_os = "linux")]
+mod ewmh;
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+mod native;
+mod replay;
+#[cfg(target_os = "linux")]
+mod wayland;
+#[cfg(target_os = "linux")]
+mod x11;
+
+pub use replay::ReplaySource;
+
+#[derive(Debug, Clone, Copy)]
+pub enum CaptureType {
+    Screen,
+    ActiveWindow,
+}
+
+pub struct CapturedScreenshot {
+    pub image: RgbImage,
+    pub app_name: String,
+    pub title: String,
+    /// When the screenshot was taken.
+    pub timestamp: OffsetDateTime,
+}
+
+/// Something that can take screenshots, e.g. a platform capture API.
+#[async_trait]
+pub trait CaptureSource: Send + Sync {
+    /// Takes a screenshot and reports which window was active while it was taken.
+    /// Returns `None` once a finite source has no more screenshots, live sources never do.
+    async fn capture(&self, capture_type: CaptureType) -> Result<Option<CapturedScreenshot>>;
+
+    /// Whether the recorder waits for the screenshot interval between captures. Sources that
+    /// replay recorded screenshots are captured as fast as possible instead.
+    fn is_live(&self) -> bool {
+        true
+    }
+}
+
+/// Picks the capture backend for the current platform and session.
+pub async fn default_source() -> Result<Box<dyn CaptureSource>> {
+    #[cfg(any(target_os = "windows", target_os = "macos"))]
+    {
+        let source = native::CrabGrabSource::new().await?;
+        Ok(Box::new(source))
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::env;
+
+        use tracing::info;
+
+        let is_wayland = env::var_os("WAYLAND_DISPLAY").is_some()
+            || env::var("XDG_SESSION_TYPE").is_ok_and(|t| t == "wayland");
+        if is_wayland {
+            info!("using xdg-desktop-portal capture backend");
+            Ok(Box::new(wayland::PortalSource::new()))
+        } else {
+            info!("using X11 capture backend");
+            Ok(Box::new(x11::X11Source::new()?))
+        }
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+    {
+        color_eyre::eyre::bail!("screen capture is not supported on this platform")
+    }
+}
@@ -0,0 +1,96 @@
+//! Capture backend for Windows and macOS, built on crabgrab.
+
+use async_trait::async_trait;
+use color_eyre::eyre::{bail, eyre, OptionExt};
+use color_eyre::Result;
+use crabgrab::capturable_content::{CapturableContent, CapturableContentFilter};
+use crabgrab::capture_stream::{
+    CaptureAccessToken, CaptureConfig, CapturePixelFormat, CaptureStream,
+};
+use crabgrab::feature::screenshot;
+use crabgrab::prelude::{FrameBitmap, VideoFrameBitmap};
+use image::{DynamicImage, ImageBuffer, RgbaImage};
+use time::OffsetDateTime;
+use tracing::{info, instrument};
+
+use super::{CaptureSource, CaptureType, CapturedScreenshot};
+
+pub struct CrabGrabSource {
+    access_token: CaptureAccessToken,
+}
+
+impl CrabGrabSource {
+    pub async fn new() -> Result<Self> {
+        let access_token = CaptureStream::test_access(false);
+        let access_token = match access_token {
+            Some(t) => t,
+            None => CaptureStream::request_access(false)
+                .await
+                .ok_or_eyre("unable to get capture permission")?,
+        };
+
+        Ok(Self { access_token })
+    }
+}
+
+#[async_trait]
+impl CaptureSource for CrabGrabSource {
+    #[instrument(skip(self))]
+    async fn capture(&self, capture_type: CaptureType) -> Result<Option<CapturedScreenshot>> {
+        let filter = CapturableContentFilter::EVERYTHING_NORMAL;
+        let content = CapturableContent::new(filter).await?;
+        // supported by both windows and macos
+        let format = CapturePixelFormat::Bgra8888;
+
+        let active_window = active_win_pos_rs::get_active_window()
+            .map_err(|_| eyre!("unable to get active window"))?;
+        info!("active window: {:?}", active_window);
+
+        let window = content
+            .windows()
+            .find(|w| w.application().pid() == active_window.process_id as i32)
+            .ok_or_eyre("could not find active window")?;
+
+        let app_name = window.application().name();
+        let title = window.title();
+        info!("capturing window: {} - {}", app_name, title);
+
+        let config = match capture_type {
+            CaptureType::Screen => {
+                CaptureConfig::with_display(content.displays().next().unwrap(), format)
+            }
+            CaptureType::ActiveWindow => CaptureConfig::with_window(window, format)?,
+        };
+
+        let video_frame = screenshot::take_screenshot(self.access_token, config).await?;
+        let bitmap = video_frame.get_bitmap()?;
+        let pixels = match bitmap {
+            FrameBitmap::BgraUnorm8x4(frame) => frame,
+            FrameBitmap::ArgbUnormPacked2101010(_) => {
+                bail!("unsupported pixel format ArgbUnormPacked2101010")
+            }
+            FrameBitmap::RgbaF16x4(_) => bail!("unsupported pixel format RgbaF16x4"),
+            FrameBitmap::YCbCr(_) => bail!("unsupported pixel format YCbCr"),
+        };
+
+        // TODO this is probably inefficient...
+        let data: Vec<_> = pixels
+            .data
+            .iter()
+            .copied()
+            .flat_map(|[b, g, r, a]| [r, g, b, a])
+            .collect();
+
+        let image: RgbaImage =
+            ImageBuffer::from_raw(pixels.width as u32, pixels.height as u32, data)
+                .ok_or_eyre("unable to create image buffer")?;
+        let image = DynamicImage::from(image).to_rgb8();
+
+        Ok(Some(CapturedScreenshot {
+            image,
+            app_name,
+            title,
+            timestamp: OffsetDateTime::now_utc(),
+        }))
+    }
+}
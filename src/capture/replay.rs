@@ -0,0 +1,95 @@
+//! Capture source that replays screenshots from a directory instead of capturing the
+//! screen, so the recording pipeline can run headlessly and deterministically. Frames are
+//! replayed back to back and keep the timestamps of the manifest.
+
+use std::fs::File;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+
+use super::{CaptureSource, CaptureType, CapturedScreenshot};
+
+/// Name of the manifest file inside a replay directory.
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// One entry of `manifest.json`, e.g. `{ "file": "0001.png", "applicationName": "Firefox",
+/// "windowTitle": "Rust docs", "timestamp": "2026-10-17T09:00:00Z" }`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Frame {
+    /// Image file, relative to the replay directory.
+    file: Utf8PathBuf,
+    application_name: String,
+    window_title: String,
+    /// When the frame was taken. Frames without a timestamp are taken one second after the
+    /// previous frame, the first one at the Unix epoch.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    timestamp: Option<OffsetDateTime>,
+}
+
+pub struct ReplaySource {
+    directory: Utf8PathBuf,
+    frames: Vec<Frame>,
+    position: AtomicUsize,
+}
+
+impl ReplaySource {
+    /// Loads the manifest of a replay directory. Frames are replayed in manifest order.
+    pub fn new(directory: impl AsRef<Utf8Path>) -> Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        let manifest_path = directory.join(MANIFEST_FILE_NAME);
+        let file = File::open(&manifest_path)
+            .wrap_err_with(|| format!("unable to open {manifest_path}"))?;
+        let mut frames: Vec<Frame> = serde_json::from_reader(file)
+            .wrap_err_with(|| format!("unable to deserialize {manifest_path}"))?;
+        let mut previous = None;
+        for frame in &mut frames {
+            let timestamp = frame.timestamp.unwrap_or_else(|| match previous {
+                Some(previous) => previous + Duration::SECOND,
+                None => OffsetDateTime::UNIX_EPOCH,
+            });
+            frame.timestamp = Some(timestamp);
+            previous = Some(timestamp);
+        }
+        info!("replaying {} frames from {directory}", frames.len());
+
+        Ok(Self {
+            directory,
+            frames,
+            position: AtomicUsize::new(0),
+        })
+    }
+}
+
+#[async_trait]
+impl CaptureSource for ReplaySource {
+    async fn capture(&self, _capture_type: CaptureType) -> Result<Option<CapturedScreenshot>> {
+        let position = self.position.fetch_add(1, Ordering::SeqCst);
+        let Some(frame) = self.frames.get(position) else {
+            return Ok(None);
+        };
+
+        let path = self.directory.join(&frame.file);
+        let bytes = tokio::fs::read(&path)
+            .await
+            .wrap_err_with(|| format!("unable to read {path}"))?;
+        let image = image::load_from_memory(&bytes)?.into_rgb8();
+
+        Ok(Some(CapturedScreenshot {
+            image,
+            app_name: frame.application_name.clone(),
+            title: frame.window_title.clone(),
+            timestamp: frame.timestamp.unwrap_or(OffsetDateTime::UNIX_EPOCH),
+        }))
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,76 @@
+//! Capture backend for Wayland sessions, built on the xdg-desktop-portal screenshot interface.
+
+use ashpd::desktop::screenshot::Screenshot;
+use async_trait::async_trait;
+use camino::Utf8PathBuf;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use time::OffsetDateTime;
+use tracing::{debug, info, instrument, warn};
+use x11rb::connection::Connection;
+
+use super::{ewmh, CaptureSource, CaptureType, CapturedScreenshot};
+use crate::encryption;
+
+pub struct PortalSource;
+
+impl PortalSource {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+/// Wayland has no standard way to query the focused window, but applications running
+/// through XWayland are still visible to EWMH.
+fn xwayland_active_window() -> Result<Option<ewmh::ActiveWindow>> {
+    let (connection, screen_number) = x11rb::connect(None)?;
+    let root = connection.setup().roots[screen_number].root;
+    ewmh::active_window(&connection, root)
+}
+
+#[async_trait]
+impl CaptureSource for PortalSource {
+    #[instrument(skip(self))]
+    async fn capture(&self, capture_type: CaptureType) -> Result<Option<CapturedScreenshot>> {
+        if let CaptureType::ActiveWindow = capture_type {
+            debug!("the screenshot portal can only capture the whole screen");
+        }
+
+        let response = Screenshot::request()
+            .interactive(false)
+            .modal(false)
+            .send()
+            .await?
+            .response()?;
+        let uri = response.uri();
+        let path = uri
+            .to_file_path()
+            .map_err(|_| eyre!("screenshot portal returned a non-file URI: {uri}"))?;
+        let path = Utf8PathBuf::try_from(path)?;
+
+        // the portal writes an unencrypted copy of the screenshot, don't leave it lying around
+        let bytes = tokio::fs::read(&path).await;
+        encryption::shred_file(&path).await?;
+        let image = image::load_from_memory(&bytes?)?.into_rgb8();
+
+        let active_window = match tokio::task::spawn_blocking(xwayland_active_window).await? {
+            Ok(window) => window,
+            Err(e) => {
+                warn!("unable to get active window: {e}");
+                None
+            }
+        };
+        // like on X11, native Wayland windows have no application name or title
+        let (app_name, title) = active_window
+            .map(|w| (w.app_name, w.title))
+            .unwrap_or_default();
+        info!("capturing window: {} - {}", app_name, title);
+
+        Ok(Some(CapturedScreenshot {
+            image,
+            app_name,
+            title,
+            timestamp: OffsetDateTime::now_utc(),
+        }))
+    }
+}
@@ -0,0 +1,159 @@
+//! Capture backend for X11 sessions, built on XGetImage.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use color_eyre::eyre::{bail, OptionExt};
+use color_eyre::Result;
+use image::RgbImage;
+use time::OffsetDateTime;
+use tracing::{info, instrument};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{ConnectionExt, ImageFormat, Screen, Window};
+use x11rb::rust_connection::RustConnection;
+
+use super::{ewmh, CaptureSource, CaptureType, CapturedScreenshot};
+
+pub struct X11Source {
+    connection: Arc<RustConnection>,
+    screen_number: usize,
+}
+
+impl X11Source {
+    pub fn new() -> Result<Self> {
+        let (connection, screen_number) = x11rb::connect(None)?;
+
+        Ok(Self {
+            connection: Arc::new(connection),
+            screen_number,
+        })
+    }
+}
+
+struct Rectangle {
+    x: i16,
+    y: i16,
+    width: u16,
+    height: u16,
+}
+
+impl Rectangle {
+    fn screen(screen: &Screen) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: screen.width_in_pixels,
+            height: screen.height_in_pixels,
+        }
+    }
+}
+
+/// The on-screen area of `window` in root window coordinates, clipped to the screen, since
+/// XGetImage fails for areas outside of the root window.
+fn window_rectangle(
+    connection: &RustConnection,
+    screen: &Screen,
+    window: Window,
+) -> Result<Option<Rectangle>> {
+    let geometry = connection.get_geometry(window)?.reply()?;
+    let position = connection
+        .translate_coordinates(window, screen.root, 0, 0)?
+        .reply()?;
+
+    let left = i32::from(position.dst_x).max(0);
+    let top = i32::from(position.dst_y).max(0);
+    let right = (i32::from(position.dst_x) + i32::from(geometry.width))
+        .min(i32::from(screen.width_in_pixels));
+    let bottom = (i32::from(position.dst_y) + i32::from(geometry.height))
+        .min(i32::from(screen.height_in_pixels));
+
+    if right <= left || bottom <= top {
+        Ok(None)
+    } else {
+        Ok(Some(Rectangle {
+            x: left as i16,
+            y: top as i16,
+            width: (right - left) as u16,
+            height: (bottom - top) as u16,
+        }))
+    }
+}
+
+fn capture_blocking(
+    connection: &RustConnection,
+    screen_number: usize,
+    capture_type: CaptureType,
+) -> Result<CapturedScreenshot> {
+    let screen = connection
+        .setup()
+        .roots
+        .get(screen_number)
+        .ok_or_eyre("X11 screen not found")?;
+    let active_window = ewmh::active_window(connection, screen.root)?;
+
+    let rectangle = match (capture_type, &active_window) {
+        (CaptureType::ActiveWindow, Some(window)) => {
+            window_rectangle(connection, screen, window.id)?
+                .unwrap_or_else(|| Rectangle::screen(screen))
+        }
+        _ => Rectangle::screen(screen),
+    };
+
+    let (app_name, title) = active_window
+        .map(|w| (w.app_name, w.title))
+        .unwrap_or_default();
+    info!("capturing window: {} - {}", app_name, title);
+
+    let reply = connection
+        .get_image(
+            ImageFormat::Z_PIXMAP,
+            screen.root,
+            rectangle.x,
+            rectangle.y,
+            rectangle.width,
+            rectangle.height,
+            !0,
+        )?
+        .reply()?;
+
+    // 24 and 32 bit TrueColor visuals are stored as BGRX with 4 bytes per pixel
+    let pixel_count = usize::from(rectangle.width) * usize::from(rectangle.height);
+    if reply.data.len() != pixel_count * 4 {
+        bail!(
+            "unsupported X11 pixel format with depth {} ({} bytes for {} pixels)",
+            reply.depth,
+            reply.data.len(),
+            pixel_count
+        );
+    }
+
+    let data: Vec<_> = reply
+        .data
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[2], pixel[1], pixel[0]])
+        .collect();
+    let image = RgbImage::from_raw(rectangle.width.into(), rectangle.height.into(), data)
+        .ok_or_eyre("unable to create image buffer")?;
+
+    Ok(CapturedScreenshot {
+        image,
+        app_name,
+        title,
+        timestamp: OffsetDateTime::now_utc(),
+    })
+}
+
+#[async_trait]
+impl CaptureSource for X11Source {
+    #[instrument(skip(self))]
+    async fn capture(&self, capture_type: CaptureType) -> Result<Option<CapturedScreenshot>> {
+        let connection = self.connection.clone();
+        let screen_number = self.screen_number;
+        let screenshot = tokio::task::spawn_blocking(move || {
+            capture_blocking(&connection, screen_number, capture_type)
+        })
+        .await??;
+
+        Ok(Some(screenshot))
+    }
+}
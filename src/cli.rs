@@ -0,0 +1,231 @@
+use camino::Utf8PathBuf;
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, Duration, OffsetDateTime, UtcOffset};
+
+use crate::encryption::{PassphraseSource, PASSPHRASE_ENV_VAR};
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Records your screen and lets you search what you have seen"
+)]
+pub struct Cli {
+    /// Configuration file.
+    #[arg(long, global = true, default_value = "reminisce.json")]
+    pub config: Utf8PathBuf,
+
+    /// Directory for the database and screenshots, overrides the paths in the configuration.
+    #[arg(long, global = true)]
+    pub data_dir: Option<Utf8PathBuf>,
+
+    /// Read the passphrase from this file descriptor instead of asking for it.
+    /// The passphrase can also be set with the REMINISCE_PASSPHRASE environment variable.
+    #[arg(long, global = true)]
+    pub passphrase_fd: Option<i32>,
+
+    /// What to do, records screenshots if omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    pub fn passphrase_source(&self) -> PassphraseSource {
+        match self.passphrase_fd {
+            Some(fd) => PassphraseSource::FileDescriptor(fd),
+            None if std::env::var_os(PASSPHRASE_ENV_VAR).is_some() => PassphraseSource::Environment,
+            None => PassphraseSource::Prompt,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Record screenshots and process them in the background.
+    Record {
+        /// Replay the screenshots in this directory (see `manifest.json`) instead of
+        /// capturing the screen.
+        #[arg(long)]
+        replay: Option<Utf8PathBuf>,
+    },
+
+    /// Pause recording of a running recorder. Processing continues while paused.
+    Pause {
+        /// Resume by itself after this long, e.g. `30m` or `2h`.
+        #[arg(long = "for", value_parser = parse_duration)]
+        duration: Option<Duration>,
+    },
+
+    /// Resume recording after a pause.
+    Resume,
+
+    /// Show whether a recorder is running, paused, and how much work it has queued.
+    Status,
+
+    /// Make the running recorder take a screenshot right away.
+    Capture,
+
+    /// Make the running recorder load the configuration file again.
+    Reload,
+
+    /// Shut the running recorder down.
+    Stop,
+
+    /// Search recorded screenshots.
+    Search(SearchArgs),
+
+    /// Show everything that is known about a screenshot.
+    Show {
+        /// ID of the screenshot.
+        id: i64,
+    },
+
+    /// Export screenshot metadata as JSON, optionally with decrypted images.
+    Export {
+        /// Directory to write the export to.
+        #[arg(long, short)]
+        output: Utf8PathBuf,
+
+        /// Also write decrypted PNG images.
+        #[arg(long)]
+        images: bool,
+
+        #[command(flatten)]
+        range: TimeRange,
+    },
+
+    /// Browse and search the screenshots in a web browser. The server only listens on localhost.
+    Serve {
+        /// Port to listen on.
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+    },
+
+    /// Let AI assistants search the screenshots with the Model Context Protocol over stdio.
+    Mcp,
+
+    /// Decrypt all screenshots into the screenshot directory.
+    Decrypt,
+
+    /// Delete screenshots, their images and everything derived from them.
+    Delete(DeleteArgs),
+
+    /// Delete screenshots that are older than the retention policy allows.
+    Prune {
+        /// Only show what would be deleted.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Show statistics about the recorded screenshots.
+    Stats,
+
+    /// Work with the configuration file.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    /// Check that everything needed for recording and processing is set up.
+    Doctor,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Check that the configuration file is valid.
+    Check,
+}
+
+#[derive(Debug, Args)]
+pub struct SearchArgs {
+    /// What to search for.
+    #[arg(required = true)]
+    pub query: Vec<String>,
+
+    /// Search by meaning instead of keywords.
+    #[arg(long, conflicts_with = "hybrid")]
+    pub semantic: bool,
+
+    /// Combine keyword and semantic search.
+    #[arg(long)]
+    pub hybrid: bool,
+
+    /// Only search screenshots of this application.
+    #[arg(long)]
+    pub app: Option<String>,
+
+    /// Maximum number of results.
+    #[arg(long, short = 'n', default_value_t = 20)]
+    pub limit: i64,
+
+    #[command(flatten)]
+    pub range: TimeRange,
+}
+
+#[derive(Debug, Args)]
+#[command(group(
+    ArgGroup::new("selection")
+        .required(true)
+        .multiple(true)
+        .args(["last", "from", "to", "app", "matching", "all"])
+))]
+pub struct DeleteArgs {
+    /// Delete what was recorded in this last period, e.g. `15m`, `2h` or `1d`.
+    #[arg(long, value_parser = parse_duration, conflicts_with_all = ["from", "to"])]
+    pub last: Option<Duration>,
+
+    #[command(flatten)]
+    pub range: TimeRange,
+
+    /// Only delete screenshots of this application.
+    #[arg(long)]
+    pub app: Option<String>,
+
+    /// Only delete screenshots that `reminisce search` finds for this query.
+    #[arg(long)]
+    pub matching: Option<String>,
+
+    /// Delete all screenshots.
+    #[arg(long, conflicts_with_all = ["last", "from", "to", "app", "matching"])]
+    pub all: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct TimeRange {
+    /// Only include screenshots taken at or after this time (RFC 3339 or YYYY-MM-DD).
+    #[arg(long, value_parser = parse_time)]
+    pub from: Option<OffsetDateTime>,
+
+    /// Only include screenshots taken at or before this time (RFC 3339 or YYYY-MM-DD).
+    #[arg(long, value_parser = parse_time)]
+    pub to: Option<OffsetDateTime>,
+}
+
+/// Parses an RFC 3339 timestamp, or a date which is interpreted as midnight UTC. Times are
+/// converted to UTC, which is how they are stored.
+pub fn parse_time(input: &str) -> Result<OffsetDateTime, String> {
+    if let Ok(time) = OffsetDateTime::parse(input, &Rfc3339) {
+        return Ok(time.to_offset(UtcOffset::UTC));
+    }
+
+    Date::parse(input, format_description!("[year]-[month]-[day]"))
+        .map(|date| date.midnight().assume_utc())
+        .map_err(|_| format!("invalid time \"{input}\", expected RFC 3339 or YYYY-MM-DD"))
+}
+
+/// Parses a duration like `30s`, `15m`, `2h`, `1d` or `1w`.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration \"{input}\", expected e.g. 30s, 15m, 2h or 1d");
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (amount, unit) = input.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    match unit {
+        "s" => Ok(Duration::seconds(amount)),
+        "m" => Ok(Duration::minutes(amount)),
+        "h" => Ok(Duration::hours(amount)),
+        "d" => Ok(Duration::days(amount)),
+        "w" => Ok(Duration::weeks(amount)),
+        _ => Err(invalid()),
+    }
+}
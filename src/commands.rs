@@ -0,0 +1,835 @@
+use std::sync::Arc;
+
+use age::secrecy::SecretString;
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::{bail, eyre, Context};
+use color_eyre::Result;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::capture::{self, CaptureSource, ReplaySource};
+use crate::cli::{DeleteArgs, SearchArgs, TimeRange};
+use crate::configuration::{Configuration, ConfigurationSource, ProcessingType};
+use crate::control::{self, ControlRequest, ControlResponse};
+use crate::database::{Database, DeletionFilters, Screenshot, SearchFilters};
+use crate::encryption::{self, PassphraseSource};
+use crate::exclusion::ExclusionRules;
+use crate::image_processing::llm::Description;
+use crate::lock::InstanceLock;
+use crate::pause::PauseState;
+use crate::queue::WorkQueue;
+use crate::recorder::ScreenRecorder;
+use crate::retention;
+
+pub async fn confirm_or_create_passphrase(
+    database: &Database,
+    config: &Configuration,
+    source: PassphraseSource,
+) -> Result<SecretString> {
+    let screenshot_count = database.count().await?;
+    let test_file_path = config.screenshot_directory.join(".test");
+    if screenshot_count == 0 {
+        let passphrase = encryption::get_passphrase(source, "Please create a passphrase: ")?;
+        encryption::encrypt_file(
+            test_file_path,
+            passphrase.clone(),
+            "data".as_bytes().to_vec(),
+        )
+        .await?;
+
+        Ok(passphrase)
+    } else {
+        confirm_passphrase(config, source).await
+    }
+}
+
+/// Asks for the passphrase and checks it against the one that was created first.
+async fn confirm_passphrase(
+    config: &Configuration,
+    source: PassphraseSource,
+) -> Result<SecretString> {
+    let test_file_path = config.screenshot_directory.join(".test");
+    let passphrase = encryption::get_passphrase(source, "Please enter your passphrase: ")?;
+    let result = encryption::decrypt_file(&test_file_path, &passphrase).await;
+    if result.is_err() {
+        bail!("Wrong passphrase")
+    } else {
+        Ok(passphrase)
+    }
+}
+
+pub async fn record(
+    database: Database,
+    configuration: Configuration,
+    configuration_source: ConfigurationSource,
+    passphrase_source: PassphraseSource,
+    replay: Option<Utf8PathBuf>,
+) -> Result<()> {
+    let _lock = InstanceLock::acquire(&configuration.screenshot_directory)?;
+    let source: Box<dyn CaptureSource> = match replay {
+        Some(directory) => Box::new(ReplaySource::new(directory)?),
+        None => capture::default_source().await?,
+    };
+    let passphrase =
+        confirm_or_create_passphrase(&database, &configuration, passphrase_source).await?;
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown_on_signal(shutdown.clone()));
+
+    #[cfg(unix)]
+    let control_server = control::ControlServer::bind(&configuration.screenshot_directory).await?;
+    #[cfg(not(unix))]
+    let _ = &configuration_source;
+
+    let mut work_queue =
+        WorkQueue::new(database.clone(), passphrase.clone(), configuration.clone())?;
+    let sender = work_queue.sender();
+    let retention = tokio::spawn(retention::run(
+        database.clone(),
+        configuration.retention.clone(),
+        shutdown.clone(),
+    ));
+    let screen_recorder = Arc::new(ScreenRecorder::new(
+        database.clone(),
+        source,
+        sender,
+        passphrase,
+        configuration,
+    )?);
+
+    #[cfg(unix)]
+    let control_server = tokio::spawn(control_server.run(
+        screen_recorder.clone(),
+        database.clone(),
+        configuration_source,
+        shutdown.clone(),
+    ));
+
+    let recorder = {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { screen_recorder.start(shutdown).await })
+    };
+
+    let result = work_queue.start(shutdown.clone()).await;
+    // stop everything else as well if the queue failed
+    shutdown.cancel();
+
+    // the recorder finishes the screenshot it is saving before it stops
+    match recorder.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("screen recorder failed: {e:#}"),
+        Err(e) => error!("screen recorder failed: {e}"),
+    }
+    let _ = retention.await;
+    #[cfg(unix)]
+    let _ = control_server.await;
+
+    database.close().await;
+    info!("shut down");
+    result
+}
+
+/// Cancels `shutdown` on Ctrl-C or SIGTERM. A second signal exits right away.
+async fn shutdown_on_signal(shutdown: CancellationToken) {
+    if let Err(e) = wait_for_signal().await {
+        error!("unable to listen for signals: {e}");
+        return;
+    }
+    info!("shutting down, press Ctrl-C again to exit immediately");
+    shutdown.cancel();
+
+    if wait_for_signal().await.is_ok() {
+        warn!("exiting without shutting down");
+        std::process::exit(130);
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+/// Sends a request to the running recorder and fails if it reports an error. Returns `None` if
+/// no recorder is running.
+async fn send_control_request(
+    configuration: &Configuration,
+    request: ControlRequest,
+) -> Result<Option<ControlResponse>> {
+    match control::send(&configuration.screenshot_directory, &request).await? {
+        Some(ControlResponse::Error { message }) => bail!("the recorder failed: {message}"),
+        response => Ok(response),
+    }
+}
+
+pub async fn pause(configuration: Configuration, duration: Option<time::Duration>) -> Result<()> {
+    let pause_state = PauseState::new(&configuration.screenshot_directory);
+    let request = ControlRequest::Pause {
+        seconds: duration
+            .map(|duration| u64::try_from(duration.whole_seconds()))
+            .transpose()?,
+    };
+    // without a running recorder, the pause is picked up when it starts
+    let pause = match send_control_request(&configuration, request).await? {
+        Some(_) => pause_state.current().await?,
+        None => Some(pause_state.pause(duration).await?),
+    };
+    match pause.and_then(|pause| pause.until) {
+        Some(until) => println!("recording is paused until {until}"),
+        None => println!("recording is paused until resumed"),
+    }
+    Ok(())
+}
+
+pub async fn resume(configuration: Configuration) -> Result<()> {
+    if send_control_request(&configuration, ControlRequest::Resume)
+        .await?
+        .is_none()
+    {
+        PauseState::new(&configuration.screenshot_directory)
+            .resume()
+            .await?;
+    }
+    println!("recording resumes");
+    Ok(())
+}
+
+pub async fn status(configuration: Configuration) -> Result<()> {
+    let Some(response) = send_control_request(&configuration, ControlRequest::Status).await? else {
+        println!("the recorder is not running");
+        return Ok(());
+    };
+    let ControlResponse::Status(status) = response else {
+        bail!("unexpected response from the recorder: {response:?}");
+    };
+
+    println!("the recorder is running");
+    match (status.paused, status.paused_until) {
+        (true, Some(until)) => println!("recording is paused until {until}"),
+        (true, None) => println!("recording is paused until resumed"),
+        (false, _) => println!("recording is active"),
+    }
+    match status.last_capture {
+        Some(last_capture) => println!("last screenshot: {last_capture}"),
+        None => println!("last screenshot: none since the recorder started"),
+    }
+    println!("queued jobs: {}", status.queue_length);
+    Ok(())
+}
+
+/// Sends a request that can only be handled by a running recorder.
+pub async fn control(configuration: Configuration, request: ControlRequest) -> Result<()> {
+    let message = match request {
+        ControlRequest::CaptureNow => "taking a screenshot",
+        ControlRequest::ReloadConfig => "reloaded the configuration",
+        ControlRequest::Shutdown => "the recorder is shutting down",
+        _ => "done",
+    };
+    if send_control_request(&configuration, request)
+        .await?
+        .is_none()
+    {
+        bail!("the recorder is not running");
+    }
+    println!("{message}");
+    Ok(())
+}
+
+/// Embeds a search query with the configured embedding model.
+async fn embed_query(configuration: &Configuration, query: &str) -> Result<Vec<f32>> {
+    let embedder = configuration.load_embedder()?;
+    let query = query.to_string();
+    tokio::task::spawn_blocking(move || embedder.embed(&query)).await?
+}
+
+pub async fn search(
+    database: Database,
+    configuration: Configuration,
+    arguments: SearchArgs,
+) -> Result<()> {
+    let query = arguments.query.join(" ");
+    let filters = SearchFilters {
+        from: arguments.range.from,
+        to: arguments.range.to,
+        application_name: arguments.app,
+        limit: Some(arguments.limit),
+        ..SearchFilters::default()
+    };
+    let results = if arguments.semantic {
+        let query_embedding = embed_query(&configuration, &query).await?;
+        let k = usize::try_from(arguments.limit.max(1))?;
+        database
+            .semantic_search(&query_embedding, k, &filters)
+            .await?
+    } else if arguments.hybrid {
+        let query_embedding = embed_query(&configuration, &query).await?;
+        database
+            .hybrid_search(&query, &query_embedding, &filters)
+            .await?
+    } else {
+        database.search(&query, &filters).await?
+    };
+    if results.is_empty() {
+        println!("no screenshots found for \"{query}\"");
+    }
+
+    for result in results {
+        print_heading(&result.screenshot);
+        if let Some(snippet) = result.snippet {
+            println!("    {}", snippet.replace('\n', " "));
+        }
+        for word in result.matches {
+            println!(
+                "    \"{}\" at {},{} ({}x{})",
+                word.text, word.x, word.y, word.width, word.height
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn print_heading(screenshot: &Screenshot) {
+    println!(
+        "#{} {} [{}] {}",
+        screenshot.id, screenshot.timestamp, screenshot.application_name, screenshot.window_title
+    );
+}
+
+pub async fn show(database: Database, id: i64) -> Result<()> {
+    let screenshot = database.find_by_id(id).await?;
+    print_heading(&screenshot);
+    println!("file: {}", screenshot.path);
+    println!("status: {:?}", screenshot.status);
+
+    if let Some(description) = database.find_description(id).await? {
+        println!();
+        println!("{}", description.to_text());
+    } else if let Some(text) = &screenshot.description {
+        println!();
+        println!("{text}");
+    }
+
+    let lines: Vec<_> = database
+        .find_text_blocks(id)
+        .await?
+        .into_iter()
+        .filter(|block| block.word.is_none())
+        .collect();
+    if !lines.is_empty() {
+        println!();
+        println!("text:");
+        for line in lines {
+            println!("    {}", line.text);
+        }
+    }
+
+    let jobs = database.find_jobs(id).await?;
+    if !jobs.is_empty() {
+        println!();
+        println!("processing:");
+        for job in jobs {
+            print!(
+                "    {:?}: {:?} after {} attempts",
+                job.processing_type, job.status, job.attempts
+            );
+            match job.last_error {
+                Some(error) => println!(" ({error})"),
+                None => println!(),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedScreenshot {
+    #[serde(flatten)]
+    screenshot: Screenshot,
+
+    /// Name of the decrypted image in the export directory, if images were exported.
+    image: Option<String>,
+
+    structured_description: Option<Description>,
+}
+
+pub async fn export(
+    database: Database,
+    configuration: Configuration,
+    passphrase_source: PassphraseSource,
+    output: Utf8PathBuf,
+    images: bool,
+    range: TimeRange,
+) -> Result<()> {
+    let passphrase = if images {
+        Some(confirm_or_create_passphrase(&database, &configuration, passphrase_source).await?)
+    } else {
+        None
+    };
+
+    tokio::fs::create_dir_all(&output)
+        .await
+        .wrap_err_with(|| format!("unable to create {output}"))?;
+
+    let screenshots = database
+        .find_by_time_range(range.from, range.to, None)
+        .await?;
+    let mut exported = Vec::with_capacity(screenshots.len());
+    for screenshot in screenshots {
+        let image = match &passphrase {
+            Some(passphrase) => decrypt_image(&database, &screenshot, passphrase, &output).await?,
+            None => None,
+        };
+        let structured_description = database.find_description(screenshot.id).await?;
+        exported.push(ExportedScreenshot {
+            screenshot,
+            image,
+            structured_description,
+        });
+    }
+
+    let json = serde_json::to_vec_pretty(&exported)?;
+    tokio::fs::write(output.join("screenshots.json"), json).await?;
+    println!("exported {} screenshots to {output}", exported.len());
+
+    Ok(())
+}
+
+pub async fn decrypt_screenshots(
+    database: Database,
+    passphrase: SecretString,
+    configuration: Configuration,
+) -> Result<()> {
+    let screenshots = database.find_all().await?;
+    for screenshot in screenshots {
+        info!("decrypting screenshot {}", screenshot.id);
+        decrypt_image(
+            &database,
+            &screenshot,
+            &passphrase,
+            &configuration.screenshot_directory,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Writes the decrypted image of a screenshot to `directory` and returns its file name.
+/// Returns `None` for screenshots without an image, because it was deleted or the window was
+/// excluded.
+async fn decrypt_image(
+    database: &Database,
+    screenshot: &Screenshot,
+    passphrase: &SecretString,
+    directory: &Utf8Path,
+) -> Result<Option<String>> {
+    let Some(path) = database.find_image_path(screenshot.id).await? else {
+        return Ok(None);
+    };
+    let file_name = format!("{}.png", screenshot.id);
+    let bytes = encryption::decrypt_file(&path, passphrase).await?;
+    tokio::fs::write(directory.join(&file_name), bytes).await?;
+    Ok(Some(file_name))
+}
+
+/// Deletes screenshots. A running recorder holds the instance lock and has to do it itself,
+/// otherwise the lock keeps a recorder from starting in the meantime.
+pub async fn delete(
+    configuration: Configuration,
+    passphrase_source: PassphraseSource,
+    arguments: DeleteArgs,
+) -> Result<()> {
+    let filters = DeletionFilters {
+        from: arguments
+            .last
+            .map(|last| OffsetDateTime::now_utc() - last)
+            .or(arguments.range.from),
+        to: arguments.range.to,
+        application_name: arguments.app,
+        matching: arguments.matching,
+    };
+
+    let (deleted, removed) = match InstanceLock::try_acquire(&configuration.screenshot_directory)? {
+        Some(_lock) => {
+            let database = Database::new(&configuration.database_file_name).await?;
+            confirm_or_create_passphrase(&database, &configuration, passphrase_source).await?;
+            let deleted = database.delete_matching(&filters).await?;
+            let removed = if arguments.all {
+                let directory = &configuration.screenshot_directory;
+                let now = OffsetDateTime::now_utc();
+                Some(retention::remove_orphaned_files(&database, directory, now).await?)
+            } else {
+                None
+            };
+            database.close().await;
+            (deleted, removed)
+        }
+        None => {
+            confirm_passphrase(&configuration, passphrase_source).await?;
+            match send_control_request(&configuration, ControlRequest::Delete { filters }).await? {
+                Some(ControlResponse::Deleted { screenshots }) => (screenshots, None),
+                Some(response) => bail!("unexpected response from the recorder: {response:?}"),
+                None => bail!("the recorder is running, but doesn't answer control requests"),
+            }
+        }
+    };
+    for screenshot in &deleted {
+        print!("deleted ");
+        print_heading(screenshot);
+    }
+    println!("deleted {} screenshots", deleted.len());
+
+    match removed {
+        Some(0) => {}
+        Some(removed) => println!("removed {removed} images that were not in the database"),
+        // the recorder might be about to insert the image it just saved
+        None if arguments.all => {
+            println!("images that are not in the database are removed when the recorder restarts")
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// Applies the retention policy, through the recorder if one is running.
+pub async fn prune(configuration: Configuration, dry_run: bool) -> Result<()> {
+    let retention = &configuration.retention;
+    if retention.image_days.is_none() && retention.metadata_days.is_none() {
+        println!("no retention policy is configured, everything is kept");
+        return Ok(());
+    }
+
+    let summary = match InstanceLock::try_acquire(&configuration.screenshot_directory)? {
+        Some(_lock) => {
+            let database = Database::new(&configuration.database_file_name).await?;
+            let summary = retention::prune(&database, retention, dry_run).await?;
+            database.close().await;
+            summary
+        }
+        None => {
+            match send_control_request(&configuration, ControlRequest::Prune { dry_run }).await? {
+                Some(ControlResponse::Pruned(summary)) => summary,
+                Some(response) => bail!("unexpected response from the recorder: {response:?}"),
+                None => bail!("the recorder is running, but doesn't answer control requests"),
+            }
+        }
+    };
+    let verb = if dry_run { "would delete" } else { "deleted" };
+    println!(
+        "{verb} {} images and {} screenshots",
+        summary.images, summary.screenshots
+    );
+
+    Ok(())
+}
+
+pub async fn stats(database: Database) -> Result<()> {
+    let statistics = database.statistics().await?;
+    println!("screenshots: {}", statistics.screenshots);
+    if let (Some(first), Some(last)) = (statistics.first_screenshot, statistics.last_screenshot) {
+        println!("recorded: {first} to {last}");
+    }
+    println!("pending jobs: {}", statistics.pending_jobs);
+    println!("failed jobs: {}", statistics.failed_jobs);
+    println!(
+        "images: {:.1} MB",
+        statistics.image_bytes as f64 / (1024.0 * 1024.0)
+    );
+
+    if !statistics.applications.is_empty() {
+        println!();
+        println!("applications:");
+        for application in statistics.applications {
+            println!(
+                "    {:>6}  {}",
+                application.screenshots, application.application_name
+            );
+        }
+    }
+
+    if !statistics.activities.is_empty() {
+        println!();
+        println!("activities:");
+        for activity in statistics.activities {
+            let name = match activity.activity {
+                Some(activity) => format!("{activity:?}").to_lowercase(),
+                None => "undescribed".to_string(),
+            };
+            println!("    {:>6}  {name}", activity.screenshots);
+        }
+    }
+
+    Ok(())
+}
+
+/// Problems with the configuration that will make recording or processing fail.
+fn configuration_problems(configuration: &Configuration) -> Vec<String> {
+    let mut problems = vec![];
+    if configuration.screenshot_interval.is_zero() {
+        problems.push("screenshotInterval must be greater than zero".to_string());
+    }
+    if !(0.0..=1.0).contains(&configuration.similarity_threshold) {
+        problems.push("similarityThreshold must be between 0 and 1".to_string());
+    }
+    if configuration.max_attempts == 0 {
+        problems.push("maxAttempts must be greater than zero".to_string());
+    }
+
+    if let Err(e) = ExclusionRules::new(&configuration.exclusions) {
+        problems.push(format!("{e:#}"));
+    }
+
+    let mut models: Vec<&Utf8Path> = vec![];
+    for processing_type in &configuration.processing {
+        for dependency in processing_type.required_dependencies() {
+            if !configuration.processing.contains(dependency) {
+                problems.push(format!(
+                    "{processing_type:?} needs the results of {dependency:?}, which is not enabled"
+                ));
+            }
+        }
+        match processing_type {
+            ProcessingType::Ocr => {
+                models.push(&configuration.ocr_detection_model);
+                models.push(&configuration.ocr_recognition_model);
+            }
+            ProcessingType::Embeddings => {
+                models.push(&configuration.embedding_model);
+                models.push(&configuration.embedding_tokenizer);
+            }
+            ProcessingType::Llm => {}
+        }
+    }
+    for model in models {
+        if !model.is_file() {
+            problems.push(format!(
+                "{model} does not exist, run `just download-models` to download it"
+            ));
+        }
+    }
+
+    problems
+}
+
+pub fn check_config(configuration: &Configuration, path: &Utf8Path) -> Result<()> {
+    let problems = configuration_problems(configuration);
+    if !problems.is_empty() {
+        for problem in &problems {
+            println!("{problem}");
+        }
+        bail!("{path} has {} problems", problems.len());
+    }
+
+    println!("{path} is valid");
+    Ok(())
+}
+
+fn report(check: &str, result: Result<String>) -> bool {
+    match result {
+        Ok(details) => {
+            println!("ok      {check}: {details}");
+            true
+        }
+        Err(e) => {
+            println!("FAILED  {check}: {e:#}");
+            false
+        }
+    }
+}
+
+async fn check_llm(configuration: &Configuration) -> Result<String> {
+    let url = format!("{}:{}", configuration.llm.host, configuration.llm.port);
+    reqwest::Client::new()
+        .get(&url)
+        .timeout(std::time::Duration::from_secs(5))
+        .send()
+        .await
+        .wrap_err_with(|| format!("unable to reach {url}"))?;
+    Ok(format!("{url} is reachable"))
+}
+
+pub async fn doctor(configuration: Configuration) -> Result<()> {
+    let mut healthy = true;
+
+    let problems = configuration_problems(&configuration);
+    healthy &= report(
+        "configuration",
+        if problems.is_empty() {
+            Ok("no problems found".to_string())
+        } else {
+            Err(eyre!(problems.join(", ")))
+        },
+    );
+
+    let directory = &configuration.screenshot_directory;
+    healthy &= report(
+        "screenshot directory",
+        if directory.is_dir() {
+            Ok(directory.to_string())
+        } else {
+            Err(eyre!("{directory} does not exist"))
+        },
+    );
+
+    healthy &= match Database::new(&configuration.database_file_name).await {
+        Ok(database) => report(
+            "database",
+            database
+                .count()
+                .await
+                .map(|count| format!("{count} screenshots")),
+        ),
+        Err(e) => report("database", Err(e)),
+    };
+
+    if configuration.processing.contains(&ProcessingType::Llm) {
+        healthy &= report("LLM", check_llm(&configuration).await);
+    }
+
+    healthy &= report(
+        "screen capture",
+        capture::default_source()
+            .await
+            .map(|_| "capture backend is available".to_string()),
+    );
+
+    if !healthy {
+        bail!("some checks failed");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::configuration::RetentionConfiguration;
+    use crate::database::NewScreenshot;
+
+    /// A database with a screenshot that has an image, one whose image was pruned and an
+    /// excluded one.
+    async fn history(root: &Utf8Path) -> (Database, Configuration, SecretString) {
+        let configuration = Configuration {
+            screenshot_directory: root.join("screenshots"),
+            database_file_name: root.join("reminisce.sqlite3").into_string(),
+            ..Configuration::default()
+        };
+        std::fs::create_dir_all(&configuration.screenshot_directory).unwrap();
+        let database = Database::new(&configuration.database_file_name)
+            .await
+            .unwrap();
+        let passphrase = SecretString::from("passphrase".to_string());
+
+        let now = OffsetDateTime::now_utc();
+        for (timestamp, application_name) in [(now - Duration::days(10), "Old"), (now, "New")] {
+            let path = configuration
+                .screenshot_directory
+                .join(format!("{}.png.enc", Uuid::new_v4()));
+            encryption::encrypt_file(&path, passphrase.clone(), b"image".to_vec())
+                .await
+                .unwrap();
+            database
+                .insert(NewScreenshot {
+                    path: path.into_string(),
+                    image_size: 5,
+                    timestamp,
+                    window_title: String::new(),
+                    application_name: application_name.into(),
+                })
+                .await
+                .unwrap();
+        }
+        database
+            .insert_excluded(now + Duration::seconds(1), "Secret")
+            .await
+            .unwrap();
+        let retention = RetentionConfiguration {
+            image_days: Some(1),
+            ..RetentionConfiguration::default()
+        };
+        retention::prune(&database, &retention, false)
+            .await
+            .unwrap();
+
+        (database, configuration, passphrase)
+    }
+
+    fn temp_directory() -> Utf8PathBuf {
+        Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("reminisce-commands-{}", Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn screenshots_without_images_are_skipped() {
+        let root = temp_directory();
+        let (database, configuration, passphrase) = history(&root).await;
+        encryption::encrypt_file(
+            configuration.screenshot_directory.join(".test"),
+            passphrase.clone(),
+            b"data".to_vec(),
+        )
+        .await
+        .unwrap();
+
+        std::env::set_var(encryption::PASSPHRASE_ENV_VAR, "passphrase");
+        let output = root.join("export");
+        export(
+            database.clone(),
+            configuration.clone(),
+            PassphraseSource::Environment,
+            output.clone(),
+            true,
+            TimeRange {
+                from: None,
+                to: None,
+            },
+        )
+        .await
+        .unwrap();
+        let exported: Vec<serde_json::Value> =
+            serde_json::from_slice(&std::fs::read(output.join("screenshots.json")).unwrap())
+                .unwrap();
+        let images: Vec<_> = exported
+            .iter()
+            .map(|screenshot| {
+                (
+                    screenshot["applicationName"].as_str().unwrap(),
+                    screenshot["image"].is_string(),
+                )
+            })
+            .collect();
+        assert_eq!(images, [("Old", false), ("New", true), ("Secret", false)]);
+
+        decrypt_screenshots(database, passphrase, configuration.clone())
+            .await
+            .unwrap();
+        let decrypted = std::fs::read_dir(&configuration.screenshot_directory)
+            .unwrap()
+            .filter(|file| {
+                let name = file.as_ref().unwrap().file_name();
+                name.to_string_lossy().ends_with(".png")
+            })
+            .count();
+        assert_eq!(decrypted, 1);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,23 +1,191 @@
 use std::fs::File;
 use std::time::Duration;
 
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use color_eyre::eyre::Context;
 use color_eyre::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DurationSeconds};
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+use crate::image_processing::embeddings::TextEmbedder;
+use crate::image_processing::ocr::TextRecognizer;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
 #[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
 pub enum ProcessingType {
     Llm,
     Ocr,
     Embeddings,
 }
 
+impl ProcessingType {
+    /// Processing types whose results this one uses. Jobs of this type wait until the jobs
+    /// of their dependencies have finished or failed, regardless of the configured order.
+    pub fn dependencies(self) -> &'static [ProcessingType] {
+        match self {
+            ProcessingType::Ocr => &[],
+            ProcessingType::Llm => &[ProcessingType::Ocr],
+            ProcessingType::Embeddings => &[ProcessingType::Ocr, ProcessingType::Llm],
+        }
+    }
+
+    /// Dependencies without which this processing type has nothing to work with. The others
+    /// only improve its results when they are enabled.
+    pub fn required_dependencies(self) -> &'static [ProcessingType] {
+        match self {
+            ProcessingType::Ocr | ProcessingType::Llm => &[],
+            ProcessingType::Embeddings => &[ProcessingType::Ocr],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LlmBackendType {
+    /// Ollama's native API.
+    #[default]
+    Ollama,
+    /// The OpenAI chat completions API, as served by llama.cpp, vLLM or LM Studio.
+    OpenAi,
+}
+
+/// API key of an LLM server. It is kept out of `Debug` output, so it doesn't end up in logs.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct ApiKey(String);
+
+impl ApiKey {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ApiKey(<redacted>)")
+    }
+}
+
+/// How screenshot descriptions are generated.
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LlmConfiguration {
+    /// Which API the LLM server speaks.
+    pub backend: LlmBackendType,
+
+    /// Host of the LLM server, including the scheme.
+    pub host: String,
+
+    /// Port of the LLM server.
+    pub port: u16,
+
+    /// Sent as a bearer token to OpenAI-compatible servers that require one.
+    pub api_key: Option<ApiKey>,
+
+    /// Vision model that describes the screenshots.
+    pub model: String,
+
+    /// Prompt sent along with each screenshot. `{platform}`, `{app}`, `{title}` and
+    /// `{ocr_text}` are replaced with the operating system, the name of the active
+    /// application, the window title and the OCR text of the screenshot. Instructions for
+    /// the JSON output format are appended automatically.
+    pub prompt: String,
+
+    /// Sampling temperature, uses the model's default if unset.
+    pub temperature: Option<f32>,
+
+    /// Size of the context window in tokens, uses the model's default if unset. Only
+    /// supported by Ollama, other servers set this when loading the model.
+    pub context_size: Option<u32>,
+
+    /// How long to wait for a description before giving up.
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub timeout: Duration,
+}
+
+impl Default for LlmConfiguration {
+    fn default() -> Self {
+        Self {
+            backend: LlmBackendType::Ollama,
+            host: "http://localhost".to_string(),
+            port: 11434,
+            api_key: None,
+            model: "llava-llama3".to_string(),
+            prompt: "The following image is a screenshot from a {platform} computer. The active application is {app} and the window title is \"{title}\". This text was recognized on the screen:\n\n{ocr_text}\n\nDescribe what the user is doing.".to_string(),
+            temperature: None,
+            context_size: None,
+            timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+/// How long screenshots are kept. Unset ages keep data forever.
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetentionConfiguration {
+    /// Days after which the encrypted image of a screenshot is deleted. The text, description
+    /// and other metadata are kept.
+    pub image_days: Option<u32>,
+
+    /// Days after which a screenshot is deleted completely.
+    pub metadata_days: Option<u32>,
+
+    /// How often the recorder checks for expired screenshots.
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub interval: Duration,
+}
+
+impl Default for RetentionConfiguration {
+    fn default() -> Self {
+        Self {
+            image_days: None,
+            metadata_days: None,
+            interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Windows that are never recorded.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExclusionConfiguration {
+    /// Names of applications that are never recorded, compared case-insensitively.
+    pub applications: Vec<String>,
+
+    /// Regular expressions for window titles that are never recorded. Use `(?i)` to match
+    /// case-insensitively.
+    pub window_titles: Vec<String>,
+
+    /// Parts of window titles that browsers use to mark private windows, compared
+    /// case-insensitively.
+    pub private_browsing_markers: Vec<String>,
+
+    /// Store the time and application of excluded windows, without title or image, so gaps in
+    /// the timeline can be explained.
+    pub record_excluded: bool,
+}
+
+impl Default for ExclusionConfiguration {
+    fn default() -> Self {
+        Self {
+            applications: vec![],
+            window_titles: vec![],
+            private_browsing_markers: vec![
+                "Private Browsing".to_string(),
+                "Incognito".to_string(),
+                "InPrivate".to_string(),
+            ],
+            record_excluded: false,
+        }
+    }
+}
+
 #[serde_as]
 #[derive(Debug, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct Configuration {
     /// How often the app takes a screenshot.
     #[serde_as(as = "DurationSeconds<u64>")]
@@ -37,6 +205,46 @@ pub struct Configuration {
     pub processing: Vec<ProcessingType>,
 
     pub similarity_threshold: f32,
+
+    /// How often processing a screenshot is attempted before it is marked as failed.
+    pub max_attempts: u32,
+
+    /// Number of screenshots processed with OCR in parallel.
+    pub ocr_workers: usize,
+
+    /// Number of screenshots processed with the LLM in parallel.
+    pub llm_workers: usize,
+
+    /// Number of screenshots processed with the embedding model in parallel.
+    pub embeddings_workers: usize,
+
+    /// Text detection model used for OCR.
+    pub ocr_detection_model: Utf8PathBuf,
+
+    /// Text recognition model used for OCR.
+    pub ocr_recognition_model: Utf8PathBuf,
+
+    /// Sentence embedding model used for semantic search.
+    pub embedding_model: Utf8PathBuf,
+
+    /// Tokenizer (`tokenizer.json`) of the sentence embedding model.
+    pub embedding_tokenizer: Utf8PathBuf,
+
+    /// LLM used to describe screenshots.
+    pub llm: LlmConfiguration,
+
+    /// When old screenshots are deleted.
+    pub retention: RetentionConfiguration,
+
+    /// The oldest images are deleted when all images together take up more space than this.
+    /// Their text and metadata are kept. Unlimited if unset.
+    pub max_storage_bytes: Option<u64>,
+
+    /// A warning is logged when the disk with the screenshot directory has less free space.
+    pub min_free_disk_space_bytes: u64,
+
+    /// Windows that are never recorded.
+    pub exclusions: ExclusionConfiguration,
 }
 
 impl Default for Configuration {
@@ -48,12 +256,72 @@ impl Default for Configuration {
             database_file_name: "reminisce.sqlite3".to_string(),
             processing: vec![ProcessingType::Ocr, ProcessingType::Embeddings],
             similarity_threshold: 0.9,
+            max_attempts: 5,
+            ocr_workers: 1,
+            llm_workers: 1,
+            embeddings_workers: 1,
+            ocr_detection_model: Utf8PathBuf::from("models/text-detection.rten"),
+            ocr_recognition_model: Utf8PathBuf::from("models/text-recognition.rten"),
+            embedding_model: Utf8PathBuf::from("models/embeddings.rten"),
+            embedding_tokenizer: Utf8PathBuf::from("models/embeddings-tokenizer.json"),
+            llm: LlmConfiguration::default(),
+            retention: RetentionConfiguration::default(),
+            max_storage_bytes: None,
+            min_free_disk_space_bytes: 1024 * 1024 * 1024,
+            exclusions: ExclusionConfiguration::default(),
         }
     }
 }
 
-pub fn load() -> Result<Configuration> {
-    let file = File::open("reminisce.json").wrap_err("unable to open reminisce.json")?;
+impl Configuration {
+    pub fn load_embedder(&self) -> Result<TextEmbedder> {
+        TextEmbedder::load(&self.embedding_model, &self.embedding_tokenizer)
+    }
+
+    pub fn load_text_recognizer(&self) -> Result<TextRecognizer> {
+        TextRecognizer::load(&self.ocr_detection_model, &self.ocr_recognition_model)
+    }
+
+    /// Number of workers for a processing type, at least one.
+    pub fn workers(&self, processing_type: ProcessingType) -> usize {
+        let workers = match processing_type {
+            ProcessingType::Llm => self.llm_workers,
+            ProcessingType::Ocr => self.ocr_workers,
+            ProcessingType::Embeddings => self.embeddings_workers,
+        };
+        workers.max(1)
+    }
+
+    /// Keeps the database and screenshots in `data_directory` instead of the configured paths.
+    pub fn with_data_directory(mut self, data_directory: &Utf8Path) -> Self {
+        self.screenshot_directory = data_directory.join("screenshots");
+        self.database_file_name = data_directory.join("reminisce.sqlite3").into_string();
+        self
+    }
+}
+
+/// Where the configuration comes from, so it can be loaded again while the app runs.
+#[derive(Debug, Clone)]
+pub struct ConfigurationSource {
+    pub path: Utf8PathBuf,
+
+    /// Overrides the paths of the database and screenshots, see
+    /// [`Configuration::with_data_directory`].
+    pub data_directory: Option<Utf8PathBuf>,
+}
+
+impl ConfigurationSource {
+    pub fn load(&self) -> Result<Configuration> {
+        let configuration = load(&self.path)?;
+        Ok(match &self.data_directory {
+            Some(data_directory) => configuration.with_data_directory(data_directory),
+            None => configuration,
+        })
+    }
+}
+
+pub fn load(path: &Utf8Path) -> Result<Configuration> {
+    let file = File::open(path).wrap_err_with(|| format!("unable to open {path}"))?;
     let config = serde_json::from_reader(file).wrap_err("unable to deserialize configuration")?;
     Ok(config)
 }
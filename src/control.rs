@@ -0,0 +1,309 @@
+//! Control socket of a running recorder. Clients connect to the Unix domain socket in a
+//! private directory inside the screenshot directory, send one JSON request on a single line
+//! and get one JSON response line back, e.g. `{"command":"pause","seconds":1800}` and
+//! `{"result":"ok"}`.
+
+use camino::Utf8Path;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::database::{DeletionFilters, PruneSummary, Screenshot};
+
+/// Directory in the screenshot directory that contains the socket. Only the current user can
+/// access it, so nobody else can connect to the socket, not even between binding and changing
+/// its permissions.
+pub const SOCKET_DIRECTORY_NAME: &str = ".control";
+
+/// File name of the socket in its directory.
+pub const SOCKET_FILE_NAME: &str = "reminisce.sock";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "camelCase")]
+pub enum ControlRequest {
+    Status,
+    /// Pauses recording for `seconds`, or until resumed.
+    Pause {
+        #[serde(default)]
+        seconds: Option<u64>,
+    },
+    Resume,
+    CaptureNow,
+    /// Loads the configuration file again and applies it to the recorder.
+    ReloadConfig,
+    Shutdown,
+    /// Deletes the screenshots that match all filters.
+    Delete {
+        filters: DeletionFilters,
+    },
+    /// Deletes a single screenshot.
+    DeleteScreenshot {
+        id: i64,
+    },
+    /// Applies the retention policy of the configuration file.
+    Prune {
+        #[serde(default)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecorderStatus {
+    /// Processing jobs that are waiting or running.
+    pub queue_length: i64,
+
+    /// When the last screenshot was saved since the recorder was started.
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_capture: Option<OffsetDateTime>,
+
+    pub paused: bool,
+
+    /// When a timed pause ends.
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub paused_until: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "camelCase")]
+pub enum ControlResponse {
+    Ok,
+    Status(RecorderStatus),
+    Deleted { screenshots: Vec<Screenshot> },
+    Pruned(PruneSummary),
+    Error { message: String },
+}
+
+#[cfg(unix)]
+pub use unix::{send, ControlServer};
+
+#[cfg(unix)]
+mod unix {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::sync::Arc;
+
+    use camino::{Utf8Path, Utf8PathBuf};
+    use color_eyre::eyre::{bail, Context};
+    use color_eyre::Result;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{UnixListener, UnixStream};
+    use tokio_util::sync::CancellationToken;
+    use tracing::{error, info, warn};
+
+    use super::{
+        ControlRequest, ControlResponse, RecorderStatus, SOCKET_DIRECTORY_NAME, SOCKET_FILE_NAME,
+    };
+    use crate::configuration::ConfigurationSource;
+    use crate::database::{Database, JobStatus};
+    use crate::recorder::ScreenRecorder;
+    use crate::retention;
+
+    /// Everything that requests act on.
+    struct Context {
+        recorder: Arc<ScreenRecorder>,
+        database: Database,
+        configuration_source: ConfigurationSource,
+        shutdown: CancellationToken,
+    }
+
+    pub struct ControlServer {
+        listener: UnixListener,
+        path: Utf8PathBuf,
+    }
+
+    impl ControlServer {
+        /// Creates the socket, which only the current user can connect to.
+        pub async fn bind(directory: &Utf8Path) -> Result<Self> {
+            let socket_directory = directory.join(SOCKET_DIRECTORY_NAME);
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&socket_directory)
+                .wrap_err_with(|| format!("unable to create {socket_directory}"))?;
+            // the directory might have been created with other permissions before
+            std::fs::set_permissions(&socket_directory, std::fs::Permissions::from_mode(0o700))?;
+
+            let path = socket_directory.join(SOCKET_FILE_NAME);
+            if path.exists() {
+                if UnixStream::connect(&path).await.is_ok() {
+                    bail!("another recorder is already listening on {path}");
+                }
+                // left behind by a recorder that didn't shut down cleanly
+                std::fs::remove_file(&path)?;
+            }
+
+            let listener =
+                UnixListener::bind(&path).wrap_err_with(|| format!("unable to bind {path}"))?;
+            info!("listening for control requests on {path}");
+
+            Ok(Self { listener, path })
+        }
+
+        /// Answers requests until `shutdown` is cancelled, then removes the socket.
+        pub async fn run(
+            self,
+            recorder: Arc<ScreenRecorder>,
+            database: Database,
+            configuration_source: ConfigurationSource,
+            shutdown: CancellationToken,
+        ) {
+            let context = Arc::new(Context {
+                recorder,
+                database,
+                configuration_source,
+                shutdown: shutdown.clone(),
+            });
+            loop {
+                tokio::select! {
+                    accepted = self.listener.accept() => match accepted {
+                        Ok((stream, _)) => {
+                            let context = context.clone();
+                            tokio::spawn(async move {
+                                if let Err(e) = serve_connection(stream, &context).await {
+                                    warn!("control connection failed: {e:#}");
+                                }
+                            });
+                        }
+                        Err(e) => error!("unable to accept control connection: {e}"),
+                    },
+                    _ = shutdown.cancelled() => break,
+                }
+            }
+        }
+    }
+
+    impl Drop for ControlServer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    async fn serve_connection(stream: UnixStream, context: &Context) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).await?;
+
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => {
+                info!("received control request {request:?}");
+                handle(request, context)
+                    .await
+                    .unwrap_or_else(|e| ControlResponse::Error {
+                        message: format!("{e:#}"),
+                    })
+            }
+            Err(e) => ControlResponse::Error {
+                message: format!("invalid request: {e}"),
+            },
+        };
+
+        let mut message = serde_json::to_vec(&response)?;
+        message.push(b'\n');
+        writer.write_all(&message).await?;
+        Ok(())
+    }
+
+    async fn handle(request: ControlRequest, context: &Context) -> Result<ControlResponse> {
+        let recorder = &context.recorder;
+        match request {
+            ControlRequest::Status => {
+                let pause = recorder.pause_state().current().await?;
+                let queue_length = context.database.count_jobs(JobStatus::Pending).await?
+                    + context.database.count_jobs(JobStatus::Running).await?;
+                Ok(ControlResponse::Status(RecorderStatus {
+                    queue_length,
+                    last_capture: recorder.last_capture(),
+                    paused: pause.is_some(),
+                    paused_until: pause.and_then(|pause| pause.until),
+                }))
+            }
+            ControlRequest::Pause { seconds } => {
+                let duration = seconds
+                    .map(|seconds| i64::try_from(seconds).map(time::Duration::seconds))
+                    .transpose()?;
+                recorder.pause_state().pause(duration).await?;
+                recorder.pause_changed();
+                Ok(ControlResponse::Ok)
+            }
+            ControlRequest::Resume => {
+                recorder.pause_state().resume().await?;
+                recorder.pause_changed();
+                Ok(ControlResponse::Ok)
+            }
+            ControlRequest::CaptureNow => {
+                if recorder.pause_state().current().await?.is_some() {
+                    bail!("recording is paused");
+                }
+                recorder.capture_now();
+                Ok(ControlResponse::Ok)
+            }
+            ControlRequest::ReloadConfig => {
+                let configuration = context.configuration_source.load()?;
+                recorder.reload(configuration)?;
+                info!("reloaded configuration");
+                Ok(ControlResponse::Ok)
+            }
+            ControlRequest::Shutdown => {
+                info!("shutting down on request");
+                context.shutdown.cancel();
+                Ok(ControlResponse::Ok)
+            }
+            ControlRequest::Delete { filters } => {
+                let screenshots = context.database.delete_matching(&filters).await?;
+                info!("deleted {} screenshots on request", screenshots.len());
+                Ok(ControlResponse::Deleted { screenshots })
+            }
+            ControlRequest::DeleteScreenshot { id } => {
+                let screenshots: Vec<_> = context.database.delete(id).await?.into_iter().collect();
+                info!("deleted {} screenshots on request", screenshots.len());
+                Ok(ControlResponse::Deleted { screenshots })
+            }
+            ControlRequest::Prune { dry_run } => {
+                let configuration = context.configuration_source.load()?;
+                let summary =
+                    retention::prune(&context.database, &configuration.retention, dry_run).await?;
+                Ok(ControlResponse::Pruned(summary))
+            }
+        }
+    }
+
+    /// Sends a request to the recorder. Returns `None` if no recorder is running.
+    pub async fn send(
+        directory: &Utf8Path,
+        request: &ControlRequest,
+    ) -> Result<Option<ControlResponse>> {
+        let path = directory.join(SOCKET_DIRECTORY_NAME).join(SOCKET_FILE_NAME);
+        let stream = match UnixStream::connect(&path).await {
+            Ok(stream) => stream,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e).wrap_err_with(|| format!("unable to connect to {path}")),
+        };
+
+        let (reader, mut writer) = stream.into_split();
+        let mut message = serde_json::to_vec(request)?;
+        message.push(b'\n');
+        writer.write_all(&message).await?;
+
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).await?;
+        let response =
+            serde_json::from_str(&line).wrap_err("unable to parse the response of the recorder")?;
+        Ok(Some(response))
+    }
+}
+
+/// Control sockets are only supported on Unix, so no recorder can be reached.
+#[cfg(not(unix))]
+pub async fn send(
+    _directory: &Utf8Path,
+    _request: &ControlRequest,
+) -> Result<Option<ControlResponse>> {
+    Ok(None)
+}
@@ -1,25 +1,79 @@
+use std::collections::HashMap;
+
 use age::secrecy::SecretString;
+use color_eyre::eyre::{bail, Context};
 use color_eyre::Result;
 use image::{ImageFormat, RgbImage};
+use serde::{Deserialize, Serialize};
 use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::SqlitePool;
-use time::OffsetDateTime;
-use tracing::info;
+use time::{OffsetDateTime, UtcOffset};
+use tracing::{error, info};
 
+use crate::configuration::ProcessingType;
 use crate::encryption;
+use crate::image_processing::embeddings;
+use crate::image_processing::llm::{Activity, Description};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+/// Default number of results returned by [`Database::search`].
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+
+/// Dampens the influence of the top ranks in reciprocal rank fusion, 60 is the value
+/// suggested in the original paper.
+const RRF_K: f64 = 60.0;
+
+/// Number of screenshots looked at at once when evicting images over the storage quota.
+const EVICTION_BATCH_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
 pub enum ProcessingStatus {
     Pending,
     Finished,
+    /// At least one processing job ran out of attempts.
+    Failed,
+    /// The window matched an exclusion rule, only the time and application were recorded.
+    Excluded,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Finished,
+    Failed,
+}
+
+/// One processing step for a single screenshot.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub screenshot_id: i64,
+
+    pub processing_type: ProcessingType,
+
+    pub status: JobStatus,
+
+    /// How often the job has been started, including the current attempt.
+    pub attempts: i64,
+
+    /// Error of the most recent failed attempt.
+    pub last_error: Option<String>,
+
+    /// The job won't be retried before this time.
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub next_attempt_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Screenshot {
     /// Sequential ID of the screenshot in the database
     pub id: i64,
 
     /// When the screenshot was taken
+    #[serde(with = "time::serde::rfc3339")]
     pub timestamp: OffsetDateTime,
 
     /// Path to the encrypted screenshot file on disk
@@ -55,9 +109,280 @@ impl Screenshot {
     }
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplicationCount {
+    pub application_name: String,
+    pub screenshots: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityCount {
+    /// `None` for screenshots without a description.
+    pub activity: Option<Activity>,
+    pub screenshots: i64,
+}
+
+/// Overview of everything that was recorded.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Statistics {
+    pub screenshots: i64,
+
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub first_screenshot: Option<OffsetDateTime>,
+
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_screenshot: Option<OffsetDateTime>,
+
+    pub pending_jobs: i64,
+
+    pub failed_jobs: i64,
+
+    /// Total size of the encrypted images in bytes.
+    pub image_bytes: i64,
+
+    /// Applications with the most screenshots, most screenshots first.
+    pub applications: Vec<ApplicationCount>,
+
+    pub activities: Vec<ActivityCount>,
+}
+
+/// What a prune deleted, or would delete.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneSummary {
+    /// Encrypted image files, including those of deleted screenshots.
+    pub images: u64,
+
+    /// Screenshots that were deleted with everything derived from them.
+    pub screenshots: u64,
+}
+
+/// Kinds of entities that the LLM extracts from a screenshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum EntityKind {
+    Person,
+    Url,
+    Keyword,
+}
+
+/// A line or word of text that OCR found on a screenshot. Coordinates are in pixels of the
+/// screenshot, starting at the top left corner.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextBlock {
+    /// Index of the line on the screenshot, in reading order.
+    pub line: i64,
+
+    /// Index of the word within its line, `None` for blocks that cover a whole line.
+    pub word: Option<i64>,
+
+    pub text: String,
+
+    pub x: i64,
+
+    pub y: i64,
+
+    pub width: i64,
+
+    pub height: i64,
+
+    /// Recognition confidence between 0 and 1, if the OCR engine reports one.
+    pub confidence: Option<f64>,
+}
+
+/// Optional constraints applied to a search.
+#[derive(Debug, Default, Clone)]
+pub struct SearchFilters {
+    /// Only return screenshots taken at or after this time.
+    pub from: Option<OffsetDateTime>,
+
+    /// Only return screenshots taken at or before this time.
+    pub to: Option<OffsetDateTime>,
+
+    /// Only return screenshots of this application.
+    pub application_name: Option<String>,
+
+    /// Only return screenshots the LLM classified as this activity.
+    pub activity: Option<Activity>,
+
+    /// Maximum number of results.
+    pub limit: Option<i64>,
+}
+
+/// Which screenshots to delete. Screenshots have to match all filters, no filters match all
+/// screenshots.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletionFilters {
+    /// Only delete screenshots taken at or after this time.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<OffsetDateTime>,
+
+    /// Only delete screenshots taken at or before this time.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<OffsetDateTime>,
+
+    /// Only delete screenshots of this application.
+    pub application_name: Option<String>,
+
+    /// Only delete screenshots that a keyword search for this finds.
+    pub matching: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    pub screenshot: Screenshot,
+
+    /// Excerpt of the matching text, with matches wrapped in `**`.
+    /// Only keyword matches have a snippet.
+    pub snippet: Option<String>,
+
+    /// Relevance of the match, higher is better. Scores are only comparable between
+    /// results of the same search.
+    pub score: f64,
+
+    /// Words on the screenshot that match the query. Only keyword matches have these.
+    pub matches: Vec<TextBlock>,
+}
+
+struct SearchRow {
+    id: i64,
+    timestamp: OffsetDateTime,
+    path: String,
+    description: Option<String>,
+    status: ProcessingStatus,
+    window_title: String,
+    application_name: String,
+    text_content: Option<String>,
+    snippet: String,
+    rank: f64,
+}
+
+impl From<SearchRow> for SearchResult {
+    fn from(row: SearchRow) -> Self {
+        Self {
+            screenshot: Screenshot {
+                id: row.id,
+                timestamp: row.timestamp,
+                path: row.path,
+                description: row.description,
+                text_content: row.text_content,
+                status: row.status,
+                window_title: row.window_title,
+                application_name: row.application_name,
+            },
+            snippet: Some(row.snippet),
+            // bm25() returns lower values for better matches
+            score: -row.rank,
+            matches: vec![],
+        }
+    }
+}
+
+/// A deleted screenshot, and whether its image still has to be removed.
+struct DeletedRow {
+    has_image: bool,
+    id: i64,
+    timestamp: OffsetDateTime,
+    path: String,
+    description: Option<String>,
+    status: ProcessingStatus,
+    window_title: String,
+    application_name: String,
+    text_content: Option<String>,
+}
+
+impl From<DeletedRow> for Screenshot {
+    fn from(row: DeletedRow) -> Self {
+        Self {
+            id: row.id,
+            timestamp: row.timestamp,
+            path: row.path,
+            description: row.description,
+            text_content: row.text_content,
+            status: row.status,
+            window_title: row.window_title,
+            application_name: row.application_name,
+        }
+    }
+}
+
+/// Timestamps are stored as UTC text and compared as text, so every timestamp that goes into
+/// a query is converted to UTC first.
+fn utc(timestamp: OffsetDateTime) -> OffsetDateTime {
+    timestamp.to_offset(UtcOffset::UTC)
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let norm_a = a.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+/// Shreds an encrypted image, a file that is already gone counts as removed.
+async fn remove_image_file(path: &str) -> Result<()> {
+    match encryption::shred_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).wrap_err_with(|| format!("unable to remove {path}")),
+    }
+}
+
+/// Shreds the images of rows that were already committed as deleted. Shredding happens
+/// outside of transactions, so the database isn't locked while files are overwritten. Files
+/// that can't be removed are logged and cleaned up as orphans on the next start.
+async fn remove_image_files<'a>(paths: impl IntoIterator<Item = &'a str>) {
+    for path in paths {
+        if let Err(e) = remove_image_file(path).await {
+            error!("{e:#}");
+        }
+    }
+}
+
+/// Turns free-form user input into an FTS5 query that matches screenshots containing
+/// every term as a prefix. Quoting each term keeps punctuation in the input from being
+/// interpreted as FTS5 syntax.
+fn to_fts_query(input: &str) -> String {
+    input
+        .split_whitespace()
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits text into lowercase tokens like the `unicode61` tokenizer of the full-text index,
+/// which treats everything but letters and digits as a separator.
+fn fts_tokens(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Whether any token of `text` starts with any of the `terms`.
+fn matches_terms(text: &str, terms: &[String]) -> bool {
+    fts_tokens(text)
+        .iter()
+        .any(|token| terms.iter().any(|term| token.starts_with(term.as_str())))
+}
+
 #[derive(Debug)]
 pub struct NewScreenshot {
     pub path: String,
+
+    /// Size of the encrypted image file in bytes.
+    pub image_size: i64,
+
     pub timestamp: OffsetDateTime,
     pub window_title: String,
     pub application_name: String,
@@ -74,10 +399,17 @@ impl Database {
             .filename(file_name)
             .create_if_missing(true);
         let pool = SqlitePool::connect_with(options).await?;
+        sqlx::migrate!().run(&pool).await?;
 
         Ok(Self { pool })
     }
 
+    /// Waits for running queries and closes all connections, after which the database
+    /// can't be used anymore.
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
     pub async fn find_by_id(&self, id: i64) -> Result<Screenshot> {
         sqlx::query_as!(
             Screenshot,
@@ -91,11 +423,26 @@ impl Database {
         .map_err(From::from)
     }
 
+    /// Path of the image of a screenshot, `None` if the image was deleted.
+    pub async fn find_image_path(&self, id: i64) -> Result<Option<String>> {
+        let path = sqlx::query_scalar!(
+            "SELECT IIF(image_deleted_at IS NULL, path, '') AS \"path!: String\"
+            FROM screenshots
+            WHERE rowid = ?",
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(Some(path).filter(|path| !path.is_empty()))
+    }
+
+    /// The most recent screenshot whose image still exists.
     pub async fn find_most_recent_screenshot(&self) -> Result<Option<Screenshot>> {
         sqlx::query_as!(
             Screenshot,
             "SELECT rowid AS id, timestamp AS \"timestamp: _\", path, description, status AS \"status: _\", window_title, application_name, text_content
             FROM screenshots
+            WHERE image_deleted_at IS NULL
             ORDER BY timestamp DESC
             LIMIT 1"
         )
@@ -104,39 +451,218 @@ impl Database {
         .map_err(From::from)
     }
 
-    pub async fn update_description(&self, id: i64, description: &str) -> Result<()> {
+    /// Stores the LLM description of a screenshot, replacing any previous description.
+    pub async fn update_description(&self, id: i64, description: &Description) -> Result<()> {
+        let text = description.to_text();
+        let mut transaction = self.pool.begin().await?;
         sqlx::query!(
-            "UPDATE screenshots SET description = ?, status = ? WHERE rowid = ?",
-            description,
-            ProcessingStatus::Finished,
+            "UPDATE screenshots SET description = ?, summary = ?, activity = ?, project = ? WHERE rowid = ?",
+            text,
+            description.summary,
+            description.activity,
+            description.project,
             id
         )
-        .execute(&self.pool)
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM screenshot_entities WHERE screenshot_id = ?",
+            id
+        )
+        .execute(&mut *transaction)
         .await?;
+        let entities = [
+            (EntityKind::Person, &description.people),
+            (EntityKind::Url, &description.urls),
+            (EntityKind::Keyword, &description.keywords),
+        ];
+        for (kind, values) in entities {
+            for value in values {
+                sqlx::query!(
+                    "INSERT INTO screenshot_entities (screenshot_id, kind, value) VALUES (?, ?, ?)",
+                    id,
+                    kind,
+                    value
+                )
+                .execute(&mut *transaction)
+                .await?;
+            }
+        }
+        transaction.commit().await?;
 
         Ok(())
     }
 
-    pub async fn update_text_content(&self, id: i64, text_content: &str) -> Result<()> {
+    /// The structured LLM description of a screenshot, if it has one.
+    pub async fn find_description(&self, id: i64) -> Result<Option<Description>> {
+        let row = sqlx::query!(
+            "SELECT summary AS \"summary!\", activity AS \"activity: Activity\", project
+            FROM screenshots
+            WHERE rowid = ? AND summary IS NOT NULL",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let entities = sqlx::query!(
+            "SELECT kind AS \"kind: EntityKind\", value FROM screenshot_entities WHERE screenshot_id = ? ORDER BY rowid",
+            id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let values = |kind: EntityKind| -> Vec<String> {
+            entities
+                .iter()
+                .filter(|e| e.kind == kind)
+                .map(|e| e.value.clone())
+                .collect()
+        };
+
+        Ok(Some(Description {
+            summary: row.summary,
+            activity: row.activity.unwrap_or(Activity::Other),
+            project: row.project,
+            people: values(EntityKind::Person),
+            urls: values(EntityKind::Url),
+            keywords: values(EntityKind::Keyword),
+        }))
+    }
+
+    /// Number of screenshots per activity in a time range. Screenshots without a
+    /// description are counted with no activity.
+    pub async fn count_by_activity(
+        &self,
+        from: Option<OffsetDateTime>,
+        to: Option<OffsetDateTime>,
+    ) -> Result<Vec<(Option<Activity>, i64)>> {
+        let (from, to) = (from.map(utc), to.map(utc));
+        let rows = sqlx::query!(
+            "SELECT activity AS \"activity: Activity\", COUNT(*) AS \"count!: i64\"
+            FROM screenshots
+            WHERE (? IS NULL OR timestamp >= ?) AND (? IS NULL OR timestamp <= ?)
+            GROUP BY activity
+            ORDER BY COUNT(*) DESC",
+            from,
+            from,
+            to,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| (r.activity, r.count)).collect())
+    }
+
+    /// Stores the OCR result of a screenshot, replacing any previous result.
+    pub async fn update_text_content(
+        &self,
+        id: i64,
+        text_content: &str,
+        text_blocks: &[TextBlock],
+    ) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
         sqlx::query!(
-            "UPDATE screenshots SET text_content = ?, status = ? WHERE rowid = ?",
+            "UPDATE screenshots SET text_content = ? WHERE rowid = ?",
             text_content,
-            ProcessingStatus::Finished,
             id
         )
-        .execute(&self.pool)
+        .execute(&mut *transaction)
         .await?;
 
+        sqlx::query!("DELETE FROM text_blocks WHERE screenshot_id = ?", id)
+            .execute(&mut *transaction)
+            .await?;
+        for block in text_blocks {
+            sqlx::query!(
+                "INSERT INTO text_blocks (screenshot_id, line, word, text, x, y, width, height, confidence)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                id,
+                block.line,
+                block.word,
+                block.text,
+                block.x,
+                block.y,
+                block.width,
+                block.height,
+                block.confidence
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
         Ok(())
     }
 
+    /// All lines and words OCR found on a screenshot, in reading order.
+    pub async fn find_text_blocks(&self, id: i64) -> Result<Vec<TextBlock>> {
+        sqlx::query_as!(
+            TextBlock,
+            "SELECT line, word, text, x, y, width, height, confidence
+            FROM text_blocks
+            WHERE screenshot_id = ?
+            ORDER BY line, word",
+            id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(From::from)
+    }
+
+    /// Words on the given screenshots that start with any of the terms in `query`, like the
+    /// prefix matching of [`Database::search`]. Returns the words by screenshot ID.
+    pub async fn find_matching_words(
+        &self,
+        ids: &[i64],
+        query: &str,
+    ) -> Result<HashMap<i64, Vec<TextBlock>>> {
+        let terms = fts_tokens(query);
+        let ids = serde_json::to_string(ids)?;
+        let rows = sqlx::query!(
+            "SELECT screenshot_id, line, word, text, x, y, width, height, confidence
+            FROM text_blocks
+            WHERE screenshot_id IN (SELECT value FROM json_each(?)) AND word IS NOT NULL
+            ORDER BY screenshot_id, line, word",
+            ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut matches: HashMap<i64, Vec<TextBlock>> = HashMap::new();
+        for row in rows {
+            if !matches_terms(&row.text, &terms) {
+                continue;
+            }
+            matches
+                .entry(row.screenshot_id)
+                .or_default()
+                .push(TextBlock {
+                    line: row.line,
+                    word: row.word,
+                    text: row.text,
+                    x: row.x,
+                    y: row.y,
+                    width: row.width,
+                    height: row.height,
+                    confidence: row.confidence,
+                });
+        }
+        Ok(matches)
+    }
+
     pub async fn insert(&self, screenshot: NewScreenshot) -> Result<Screenshot> {
         info!("inserting screenshot {screenshot:?} into database");
+        let timestamp = utc(screenshot.timestamp);
         let result = sqlx::query!(
-            "INSERT INTO screenshots (timestamp, path, description, status, window_title, application_name)
-             VALUES (?, ?, ?, ?, ?, ?) RETURNING rowid",
-            screenshot.timestamp,
+            "INSERT INTO screenshots (timestamp, path, image_size, description, status, window_title, application_name)
+             VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING rowid",
+            timestamp,
             screenshot.path,
+            screenshot.image_size,
             None::<String>,
             ProcessingStatus::Pending,
             screenshot.window_title,
@@ -147,7 +673,7 @@ impl Database {
 
         Ok(Screenshot {
             id: result.rowid,
-            timestamp: screenshot.timestamp,
+            timestamp,
             path: screenshot.path.clone(),
             description: None,
             status: ProcessingStatus::Pending,
@@ -157,6 +683,42 @@ impl Database {
         })
     }
 
+    /// Records that a window was excluded from recording. Only the time and application are
+    /// stored, there is no image.
+    pub async fn insert_excluded(
+        &self,
+        timestamp: OffsetDateTime,
+        application_name: &str,
+    ) -> Result<()> {
+        let timestamp = utc(timestamp);
+        sqlx::query!(
+            "INSERT INTO screenshots (timestamp, path, image_size, image_deleted_at, status, window_title, application_name)
+            VALUES (?, '', 0, ?, ?, '', ?)",
+            timestamp,
+            timestamp,
+            ProcessingStatus::Excluded,
+            application_name
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn upsert_embedding(&self, id: i64, model: &str, embedding: &[f32]) -> Result<()> {
+        let bytes = embeddings::to_bytes(embedding);
+        sqlx::query!(
+            "INSERT INTO embeddings (screenshot_id, model, embedding) VALUES (?, ?, ?)
+             ON CONFLICT (screenshot_id) DO UPDATE SET model = excluded.model, embedding = excluded.embedding",
+            id,
+            model,
+            bytes
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn find_all(&self) -> Result<Vec<Screenshot>> {
         sqlx::query_as!(
             Screenshot,
@@ -175,6 +737,75 @@ impl Database {
         Ok(result)
     }
 
+    /// Screenshots taken in a time range, optionally of a single application, oldest first.
+    pub async fn find_by_time_range(
+        &self,
+        from: Option<OffsetDateTime>,
+        to: Option<OffsetDateTime>,
+        application_name: Option<&str>,
+    ) -> Result<Vec<Screenshot>> {
+        let (from, to) = (from.map(utc), to.map(utc));
+        sqlx::query_as!(
+            Screenshot,
+            "SELECT rowid AS id, timestamp AS \"timestamp: _\", path, description, status AS \"status: _\", window_title, application_name, text_content
+            FROM screenshots
+            WHERE (? IS NULL OR timestamp >= ?)
+                AND (? IS NULL OR timestamp <= ?)
+                AND (? IS NULL OR application_name = ?)
+            ORDER BY timestamp",
+            from,
+            from,
+            to,
+            to,
+            application_name,
+            application_name
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(From::from)
+    }
+
+    pub async fn statistics(&self) -> Result<Statistics> {
+        let range = sqlx::query!(
+            "SELECT COUNT(*) AS \"screenshots!: i64\", MIN(timestamp) AS \"first?: OffsetDateTime\", MAX(timestamp) AS \"last?: OffsetDateTime\"
+            FROM screenshots"
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let applications = sqlx::query_as!(
+            ApplicationCount,
+            "SELECT application_name, COUNT(*) AS \"screenshots!: i64\"
+            FROM screenshots
+            GROUP BY application_name
+            ORDER BY COUNT(*) DESC
+            LIMIT 10"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let activities = self
+            .count_by_activity(None, None)
+            .await?
+            .into_iter()
+            .map(|(activity, screenshots)| ActivityCount {
+                activity,
+                screenshots,
+            })
+            .collect();
+
+        Ok(Statistics {
+            screenshots: range.screenshots,
+            first_screenshot: range.first,
+            last_screenshot: range.last,
+            pending_jobs: self.count_jobs(JobStatus::Pending).await?,
+            failed_jobs: self.count_jobs(JobStatus::Failed).await?,
+            image_bytes: self.storage_usage().await?,
+            applications,
+            activities,
+        })
+    }
+
     pub async fn find_pending(&self) -> Result<Vec<Screenshot>> {
         sqlx::query_as!(
             Screenshot,
@@ -188,10 +819,592 @@ impl Database {
         .map_err(From::from)
     }
 
-    pub async fn delete_all(&self) -> Result<()> {
-        sqlx::query!("DELETE FROM screenshots")
-            .execute(&self.pool)
+    /// Full-text search over the OCR text, description, window title and application name
+    /// of all screenshots, best matches first.
+    pub async fn search(&self, query: &str, filters: &SearchFilters) -> Result<Vec<SearchResult>> {
+        let fts_query = to_fts_query(query);
+        if fts_query.is_empty() {
+            bail!("search query must not be empty");
+        }
+        let limit = filters.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        let (from, to) = (filters.from.map(utc), filters.to.map(utc));
+
+        let rows = sqlx::query_as!(
+            SearchRow,
+            "SELECT s.rowid AS \"id!\", s.timestamp AS \"timestamp: _\", s.path, s.description, s.status AS \"status: _\", s.window_title, s.application_name, s.text_content,
+                snippet(screenshots_fts, -1, '**', '**', '…', 16) AS \"snippet!: String\",
+                bm25(screenshots_fts) AS \"rank!: f64\"
+            FROM screenshots_fts
+            JOIN screenshots s ON s.rowid = screenshots_fts.rowid
+            WHERE screenshots_fts MATCH ?
+                AND (? IS NULL OR s.timestamp >= ?)
+                AND (? IS NULL OR s.timestamp <= ?)
+                AND (? IS NULL OR s.application_name = ?)
+                AND (? IS NULL OR s.activity = ?)
+            ORDER BY bm25(screenshots_fts)
+            LIMIT ?",
+            fts_query,
+            from,
+            from,
+            to,
+            to,
+            filters.application_name,
+            filters.application_name,
+            filters.activity,
+            filters.activity,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let ids: Vec<i64> = rows.iter().map(|row| row.id).collect();
+        let mut matches = self.find_matching_words(&ids, query).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let mut result = SearchResult::from(row);
+                result.matches = matches.remove(&result.screenshot.id).unwrap_or_default();
+                result
+            })
+            .collect())
+    }
+
+    /// Returns the `k` screenshots whose stored embeddings are most similar to the embedding
+    /// of a query. This is a brute-force scan over all stored embeddings, which is fast enough
+    /// for the number of screenshots a single person accumulates.
+    pub async fn semantic_search(
+        &self,
+        query_embedding: &[f32],
+        k: usize,
+        filters: &SearchFilters,
+    ) -> Result<Vec<SearchResult>> {
+        let (from, to) = (filters.from.map(utc), filters.to.map(utc));
+
+        let rows = sqlx::query!(
+            "SELECT e.screenshot_id, e.embedding
+            FROM embeddings e
+            JOIN screenshots s ON s.rowid = e.screenshot_id
+            WHERE e.model = ?
+                AND (? IS NULL OR s.timestamp >= ?)
+                AND (? IS NULL OR s.timestamp <= ?)
+                AND (? IS NULL OR s.application_name = ?)
+                AND (? IS NULL OR s.activity = ?)",
+            embeddings::MODEL_NAME,
+            from,
+            from,
+            to,
+            to,
+            filters.application_name,
+            filters.application_name,
+            filters.activity,
+            filters.activity,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut scored: Vec<_> = rows
+            .into_iter()
+            .map(|row| {
+                let embedding = embeddings::from_bytes(&row.embedding);
+                let score = cosine_similarity(query_embedding, &embedding);
+                (row.screenshot_id, score)
+            })
+            .collect();
+        scored.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        scored.truncate(k);
+
+        let ids: Vec<i64> = scored.iter().map(|(id, _)| *id).collect();
+        let ids = serde_json::to_string(&ids)?;
+        let mut screenshots: HashMap<i64, Screenshot> = sqlx::query_as!(
+            Screenshot,
+            "SELECT rowid AS id, timestamp AS \"timestamp: _\", path, description, status AS \"status: _\", window_title, application_name, text_content
+            FROM screenshots
+            WHERE rowid IN (SELECT value FROM json_each(?))",
+            ids
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|screenshot| (screenshot.id, screenshot))
+        .collect();
+
+        Ok(scored
+            .into_iter()
+            .filter_map(|(id, score)| {
+                Some(SearchResult {
+                    screenshot: screenshots.remove(&id)?,
+                    snippet: None,
+                    score: score as f64,
+                    matches: vec![],
+                })
+            })
+            .collect())
+    }
+
+    /// Combines keyword and semantic search with reciprocal rank fusion, so screenshots that
+    /// rank well in either search (and especially in both) come first.
+    pub async fn hybrid_search(
+        &self,
+        query: &str,
+        query_embedding: &[f32],
+        filters: &SearchFilters,
+    ) -> Result<Vec<SearchResult>> {
+        let limit = filters.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        let keyword_results = self.search(query, filters).await?;
+        let semantic_results = self
+            .semantic_search(query_embedding, limit as usize, filters)
+            .await?;
+
+        let mut fused: HashMap<i64, SearchResult> = HashMap::new();
+        for results in [keyword_results, semantic_results] {
+            for (rank, result) in results.into_iter().enumerate() {
+                let score = 1.0 / (RRF_K + rank as f64 + 1.0);
+                fused
+                    .entry(result.screenshot.id)
+                    .and_modify(|existing| {
+                        existing.score += score;
+                        if existing.snippet.is_none() {
+                            existing.snippet = result.snippet.clone();
+                        }
+                    })
+                    .or_insert(SearchResult { score, ..result });
+            }
+        }
+
+        let mut results: Vec<_> = fused.into_values().collect();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(limit as usize);
+
+        Ok(results)
+    }
+
+    /// Creates a pending job for every processing type that the screenshot doesn't have a job
+    /// for yet.
+    pub async fn create_jobs(&self, screenshot_id: i64, types: &[ProcessingType]) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        for processing_type in types {
+            sqlx::query!(
+                "INSERT OR IGNORE INTO jobs (screenshot_id, processing_type, status) VALUES (?, ?, ?)",
+                screenshot_id,
+                processing_type,
+                JobStatus::Pending
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Puts jobs that were running when the app stopped back into the queue.
+    pub async fn reset_running_jobs(&self) -> Result<u64> {
+        let result = sqlx::query!(
+            "UPDATE jobs SET status = ? WHERE status = ?",
+            JobStatus::Pending,
+            JobStatus::Running
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Marks the oldest due pending job of the given type as running and returns it. Jobs
+    /// whose dependencies (see [`ProcessingType::dependencies`]) are still pending or running
+    /// for the same screenshot are skipped.
+    pub async fn claim_next_job(&self, processing_type: ProcessingType) -> Result<Option<Job>> {
+        let dependencies = serde_json::to_string(processing_type.dependencies())?;
+        let now = OffsetDateTime::now_utc();
+        sqlx::query_as!(
+            Job,
+            "UPDATE jobs SET status = ?, attempts = attempts + 1
+            WHERE rowid = (
+                SELECT rowid FROM jobs AS job
+                WHERE job.status = ?
+                    AND job.processing_type = ?
+                    AND (job.next_attempt_at IS NULL OR job.next_attempt_at <= ?)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM jobs AS dependency
+                        WHERE dependency.screenshot_id = job.screenshot_id
+                            AND dependency.status IN (?, ?)
+                            AND dependency.processing_type IN (SELECT value FROM json_each(?))
+                    )
+                ORDER BY job.screenshot_id
+                LIMIT 1
+            )
+            RETURNING screenshot_id, processing_type AS \"processing_type: _\", status AS \"status: _\", attempts, last_error, next_attempt_at AS \"next_attempt_at: _\"",
+            JobStatus::Running,
+            JobStatus::Pending,
+            processing_type,
+            now,
+            JobStatus::Pending,
+            JobStatus::Running,
+            dependencies
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(From::from)
+    }
+
+    pub async fn find_jobs(&self, screenshot_id: i64) -> Result<Vec<Job>> {
+        sqlx::query_as!(
+            Job,
+            "SELECT screenshot_id, processing_type AS \"processing_type: _\", status AS \"status: _\", attempts, last_error, next_attempt_at AS \"next_attempt_at: _\"
+            FROM jobs
+            WHERE screenshot_id = ?
+            ORDER BY rowid",
+            screenshot_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(From::from)
+    }
+
+    /// Counts screenshots that are still waiting for processing, not counting those with jobs
+    /// that wait for a retry.
+    pub async fn count_unprocessed(&self) -> Result<i64> {
+        let now = OffsetDateTime::now_utc();
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM screenshots AS s
+            WHERE s.status = ?
+                AND NOT EXISTS (
+                    SELECT 1 FROM jobs
+                    WHERE jobs.screenshot_id = s.rowid
+                        AND jobs.status = ?
+                        AND jobs.next_attempt_at > ?
+                )",
+            ProcessingStatus::Pending,
+            JobStatus::Pending,
+            now
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count)
+    }
+
+    pub async fn count_jobs(&self, status: JobStatus) -> Result<i64> {
+        let result = sqlx::query_scalar!("SELECT COUNT(*) FROM jobs WHERE status = ?", status)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    pub async fn finish_job(&self, job: &Job) -> Result<()> {
+        sqlx::query!(
+            "UPDATE jobs SET status = ?, last_error = NULL, next_attempt_at = NULL
+            WHERE screenshot_id = ? AND processing_type = ?",
+            JobStatus::Finished,
+            job.screenshot_id,
+            job.processing_type
+        )
+        .execute(&self.pool)
+        .await?;
+
+        self.update_processing_status(job.screenshot_id).await
+    }
+
+    /// Records a failed attempt. The job is retried at `next_attempt_at`, or marked as failed
+    /// if there is none.
+    pub async fn fail_job(
+        &self,
+        job: &Job,
+        error: &str,
+        next_attempt_at: Option<OffsetDateTime>,
+    ) -> Result<()> {
+        let status = match next_attempt_at {
+            Some(_) => JobStatus::Pending,
+            None => JobStatus::Failed,
+        };
+        sqlx::query!(
+            "UPDATE jobs SET status = ?, last_error = ?, next_attempt_at = ?
+            WHERE screenshot_id = ? AND processing_type = ?",
+            status,
+            error,
+            next_attempt_at,
+            job.screenshot_id,
+            job.processing_type
+        )
+        .execute(&self.pool)
+        .await?;
+
+        self.update_processing_status(job.screenshot_id).await
+    }
+
+    /// Derives the processing status of a screenshot from the status of its jobs.
+    async fn update_processing_status(&self, screenshot_id: i64) -> Result<()> {
+        sqlx::query!(
+            "UPDATE screenshots SET status = CASE
+                WHEN EXISTS (SELECT 1 FROM jobs WHERE screenshot_id = ? AND status IN (?, ?)) THEN ?
+                WHEN EXISTS (SELECT 1 FROM jobs WHERE screenshot_id = ? AND status = ?) THEN ?
+                ELSE ?
+            END
+            WHERE rowid = ?",
+            screenshot_id,
+            JobStatus::Pending,
+            JobStatus::Running,
+            ProcessingStatus::Pending,
+            screenshot_id,
+            JobStatus::Failed,
+            ProcessingStatus::Failed,
+            ProcessingStatus::Finished,
+            screenshot_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Total size of the encrypted images that haven't been deleted, in bytes.
+    pub async fn storage_usage(&self) -> Result<i64> {
+        let image_bytes = sqlx::query_scalar!("SELECT image_bytes FROM storage_usage")
+            .fetch_one(&self.pool)
             .await?;
+        Ok(image_bytes)
+    }
+
+    /// Paths of all images that weren't deleted.
+    pub async fn find_image_paths(&self) -> Result<Vec<String>> {
+        let rows = sqlx::query!(
+            "SELECT path FROM screenshots WHERE path != '' AND image_deleted_at IS NULL"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.path).collect())
+    }
+
+    /// Screenshots with an image whose size isn't known yet, as ID and path.
+    pub async fn find_unmeasured_images(&self) -> Result<Vec<(i64, String)>> {
+        let rows = sqlx::query!(
+            "SELECT rowid AS id, path
+            FROM screenshots
+            WHERE image_size IS NULL AND image_deleted_at IS NULL"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| (row.id, row.path)).collect())
+    }
+
+    pub async fn set_image_size(&self, id: i64, image_size: i64) -> Result<()> {
+        sqlx::query!(
+            "UPDATE screenshots SET image_size = ? WHERE rowid = ?",
+            image_size,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
         Ok(())
     }
+
+    /// Deletes the oldest images until the images take up at most `max_bytes`. The
+    /// screenshots themselves are kept. Like [`Database::prune`], the files are removed after
+    /// the rows are committed.
+    pub async fn evict_oldest_images(&self, max_bytes: i64) -> Result<PruneSummary> {
+        let now = OffsetDateTime::now_utc();
+        let mut transaction = self.pool.begin().await?;
+        let image_bytes = sqlx::query_scalar!("SELECT image_bytes FROM storage_usage")
+            .fetch_one(&mut *transaction)
+            .await?;
+
+        let mut excess = image_bytes - max_bytes;
+        let mut paths = vec![];
+        while excess > 0 {
+            let oldest = sqlx::query!(
+                "SELECT rowid AS id, path, image_size
+                FROM screenshots
+                WHERE image_deleted_at IS NULL
+                ORDER BY timestamp
+                LIMIT ?",
+                EVICTION_BATCH_SIZE
+            )
+            .fetch_all(&mut *transaction)
+            .await?;
+            if oldest.is_empty() {
+                break;
+            }
+
+            for screenshot in oldest {
+                if excess <= 0 {
+                    break;
+                }
+                sqlx::query!(
+                    "UPDATE screenshots SET image_deleted_at = ? WHERE rowid = ?",
+                    now,
+                    screenshot.id
+                )
+                .execute(&mut *transaction)
+                .await?;
+                excess -= screenshot.image_size.unwrap_or(0);
+                paths.push(screenshot.path);
+            }
+        }
+
+        transaction.commit().await?;
+        remove_image_files(paths.iter().map(String::as_str)).await;
+
+        Ok(PruneSummary {
+            images: paths.len() as u64,
+            screenshots: 0,
+        })
+    }
+
+    /// Counts what [`Database::prune`] would delete with the same cutoffs.
+    pub async fn count_expired(
+        &self,
+        image_cutoff: Option<OffsetDateTime>,
+        metadata_cutoff: Option<OffsetDateTime>,
+    ) -> Result<PruneSummary> {
+        let (image_cutoff, metadata_cutoff) = (image_cutoff.map(utc), metadata_cutoff.map(utc));
+        let counts = sqlx::query!(
+            "SELECT
+                COALESCE(SUM(image_deleted_at IS NULL AND ((? IS NOT NULL AND timestamp < ?) OR (? IS NOT NULL AND timestamp < ?))), 0) AS \"images!: i64\",
+                COALESCE(SUM(? IS NOT NULL AND timestamp < ?), 0) AS \"screenshots!: i64\"
+            FROM screenshots",
+            image_cutoff,
+            image_cutoff,
+            metadata_cutoff,
+            metadata_cutoff,
+            metadata_cutoff,
+            metadata_cutoff
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(PruneSummary {
+            images: u64::try_from(counts.images)?,
+            screenshots: u64::try_from(counts.screenshots)?,
+        })
+    }
+
+    /// Deletes the images of screenshots taken before `image_cutoff` and the screenshots taken
+    /// before `metadata_cutoff`. The rows are committed first and the files removed afterwards.
+    pub async fn prune(
+        &self,
+        image_cutoff: Option<OffsetDateTime>,
+        metadata_cutoff: Option<OffsetDateTime>,
+    ) -> Result<PruneSummary> {
+        let (image_cutoff, metadata_cutoff) = (image_cutoff.map(utc), metadata_cutoff.map(utc));
+        let now = OffsetDateTime::now_utc();
+        let mut transaction = self.pool.begin().await?;
+
+        let deleted = sqlx::query!(
+            "DELETE FROM screenshots
+            WHERE ? IS NOT NULL AND timestamp < ?
+            RETURNING path, image_deleted_at IS NULL AS \"has_image!: bool\"",
+            metadata_cutoff,
+            metadata_cutoff
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        let expired_images = sqlx::query_scalar!(
+            "UPDATE screenshots
+            SET image_deleted_at = ?
+            WHERE image_deleted_at IS NULL AND ? IS NOT NULL AND timestamp < ?
+            RETURNING path",
+            now,
+            image_cutoff,
+            image_cutoff
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        let paths: Vec<String> = deleted
+            .iter()
+            .filter(|row| row.has_image)
+            .map(|row| row.path.clone())
+            .chain(expired_images)
+            .collect();
+        remove_image_files(paths.iter().map(String::as_str)).await;
+
+        Ok(PruneSummary {
+            images: paths.len() as u64,
+            screenshots: deleted.len() as u64,
+        })
+    }
+
+    /// Deletes a screenshot, its image and everything derived from it. Returns `None` if the
+    /// screenshot didn't exist.
+    pub async fn delete(&self, id: i64) -> Result<Option<Screenshot>> {
+        let deleted = sqlx::query_as!(
+            DeletedRow,
+            "DELETE FROM screenshots
+            WHERE rowid = ?
+            RETURNING image_deleted_at IS NULL AS \"has_image!: bool\", rowid AS id, timestamp AS \"timestamp: _\", path, description, status AS \"status: _\", window_title, application_name, text_content",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(deleted) = deleted else {
+            return Ok(None);
+        };
+
+        if deleted.has_image {
+            remove_image_files([deleted.path.as_str()]).await;
+        }
+
+        Ok(Some(Screenshot::from(deleted)))
+    }
+
+    /// Deletes the screenshots that match all filters, with their images and everything
+    /// derived from them. The images are removed after the rows are committed. Returns the
+    /// deleted screenshots, oldest first.
+    pub async fn delete_matching(&self, filters: &DeletionFilters) -> Result<Vec<Screenshot>> {
+        let fts_query = filters.matching.as_deref().map(to_fts_query);
+        if fts_query.as_deref() == Some("") {
+            bail!("search query must not be empty");
+        }
+        let (from, to) = (filters.from.map(utc), filters.to.map(utc));
+
+        let deleted = sqlx::query_as!(
+            DeletedRow,
+            "DELETE FROM screenshots
+            WHERE (? IS NULL OR timestamp >= ?)
+                AND (? IS NULL OR timestamp <= ?)
+                AND (? IS NULL OR application_name = ?)
+                AND (? IS NULL OR rowid IN (SELECT rowid FROM screenshots_fts WHERE screenshots_fts MATCH ?))
+            RETURNING image_deleted_at IS NULL AS \"has_image!: bool\", rowid AS id, timestamp AS \"timestamp: _\", path, description, status AS \"status: _\", window_title, application_name, text_content",
+            from,
+            from,
+            to,
+            to,
+            filters.application_name,
+            filters.application_name,
+            fts_query,
+            fts_query
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let paths = deleted
+            .iter()
+            .filter(|screenshot| screenshot.has_image)
+            .map(|screenshot| screenshot.path.as_str());
+        remove_image_files(paths).await;
+
+        let mut screenshots: Vec<_> = deleted.into_iter().map(Screenshot::from).collect();
+        screenshots.sort_by_key(|screenshot| screenshot.timestamp);
+
+        Ok(screenshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_match_like_the_full_text_index() {
+        let terms = fts_tokens("Rust, \"reminisce\"");
+        assert_eq!(terms, ["rust", "reminisce"]);
+        assert!(matches_terms("(Rustacean)", &terms));
+        assert!(matches_terms("github.com/reminisce", &terms));
+        assert!(matches_terms("RUST:", &terms));
+        assert!(!matches_terms("trust", &terms));
+        assert!(!matches_terms("", &terms));
+    }
 }
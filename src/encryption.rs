@@ -1,5 +1,5 @@
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::iter;
 
 use age::scrypt::Identity;
@@ -7,7 +7,9 @@ use age::secrecy::SecretString;
 use age::stream::{StreamReader, StreamWriter};
 use age::{Decryptor, Encryptor};
 use camino::Utf8Path;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
+use tokio::sync::mpsc;
 
 fn encrypting_writer(
     path: impl AsRef<Utf8Path>,
@@ -19,6 +21,11 @@ fn encrypting_writer(
     Ok(writer)
 }
 
+/// Suffix of files that are still being written. Files with it were interrupted.
+pub const PARTIAL_FILE_SUFFIX: &str = ".partial";
+
+/// Encrypts `data` into `path`. The file is written under a temporary name and renamed once
+/// it is complete, so an interruption never leaves a truncated file at `path`.
 pub async fn encrypt_file(
     path: impl AsRef<Utf8Path>,
     passphrase: SecretString,
@@ -26,9 +33,11 @@ pub async fn encrypt_file(
 ) -> Result<()> {
     let path = path.as_ref().to_path_buf();
     tokio::task::spawn_blocking(move || {
-        let mut writer = encrypting_writer(path, passphrase)?;
+        let partial_path = format!("{path}{PARTIAL_FILE_SUFFIX}");
+        let mut writer = encrypting_writer(&partial_path, passphrase)?;
         writer.write_all(&data)?;
-        writer.finish()?;
+        writer.finish()?.sync_all()?;
+        std::fs::rename(&partial_path, &path)?;
         Ok(())
     })
     .await?
@@ -61,7 +70,107 @@ pub async fn decrypt_file(
     .await?
 }
 
-pub fn get_passphrase(prompt: &str) -> Result<SecretString> {
-    let input = rpassword::prompt_password(prompt)?;
+/// Size of the chunks that files are streamed and shredded in.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Decrypt a file in the background and send the decrypted bytes in chunks, so large files
+/// don't have to be held in memory. Fails if the file can't be opened or decrypted, later
+/// errors end the stream.
+pub async fn decrypt_file_streaming(
+    path: impl AsRef<Utf8Path>,
+    passphrase: &SecretString,
+) -> Result<mpsc::Receiver<io::Result<Vec<u8>>>> {
+    let path = path.as_ref().to_path_buf();
+    let passphrase = passphrase.clone();
+    let mut reader =
+        tokio::task::spawn_blocking(move || decrypting_reader(path, passphrase)).await??;
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || loop {
+        let mut chunk = vec![0; STREAM_CHUNK_SIZE];
+        let result = match reader.read(&mut chunk) {
+            Ok(0) => return,
+            Ok(read) => {
+                chunk.truncate(read);
+                Ok(chunk)
+            }
+            Err(e) => Err(e),
+        };
+        let failed = result.is_err();
+        // the receiver is gone when the client disconnected
+        if tx.blocking_send(result).is_err() || failed {
+            return;
+        }
+    });
+    Ok(rx)
+}
+
+/// Overwrite a file with zeros before removing it, so its contents can't be recovered from
+/// the freed blocks. Copy-on-write filesystems and SSDs may still keep old copies, the
+/// contents are encrypted either way.
+pub async fn shred_file(path: impl AsRef<Utf8Path>) -> io::Result<()> {
+    let path = path.as_ref().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::OpenOptions::new().write(true).open(&path)?;
+        let mut remaining = file.metadata()?.len();
+        let zeros = vec![0; STREAM_CHUNK_SIZE];
+        while remaining > 0 {
+            let length = remaining.min(zeros.len() as u64) as usize;
+            file.write_all(&zeros[..length])?;
+            remaining -= length as u64;
+        }
+        file.sync_all()?;
+        drop(file);
+        std::fs::remove_file(&path)
+    })
+    .await?
+}
+
+/// Environment variable that the passphrase can be passed in for scripted use.
+pub const PASSPHRASE_ENV_VAR: &str = "REMINISCE_PASSPHRASE";
+
+/// Where the passphrase is read from.
+#[derive(Debug, Clone, Copy)]
+pub enum PassphraseSource {
+    /// Ask for it on the terminal.
+    Prompt,
+    /// Read it from [`PASSPHRASE_ENV_VAR`].
+    Environment,
+    /// Read it from an open file descriptor, e.g. a pipe set up by a script.
+    FileDescriptor(i32),
+}
+
+#[cfg(unix)]
+fn read_passphrase_from_fd(fd: i32) -> Result<String> {
+    if fd == 1 || fd == 2 {
+        color_eyre::eyre::bail!("file descriptor {fd} is stdout or stderr, not an input");
+    }
+
+    // opening the descriptor through /dev/fd creates a new one, so the user's descriptor is
+    // neither taken over nor closed, and one that isn't open is reported as an error
+    let path = format!("/dev/fd/{fd}");
+    let mut file = File::open(&path)
+        .map_err(|e| eyre!("unable to read the passphrase from file descriptor {fd}: {e}"))?;
+    let mut input = String::new();
+    file.read_to_string(&mut input)?;
+    Ok(input)
+}
+
+#[cfg(not(unix))]
+fn read_passphrase_from_fd(_fd: i32) -> Result<String> {
+    color_eyre::eyre::bail!(
+        "reading the passphrase from a file descriptor is only supported on unix"
+    )
+}
+
+pub fn get_passphrase(source: PassphraseSource, prompt: &str) -> Result<SecretString> {
+    let input = match source {
+        PassphraseSource::Prompt => rpassword::prompt_password(prompt)?,
+        PassphraseSource::Environment => std::env::var(PASSPHRASE_ENV_VAR)
+            .map_err(|_| eyre!("{PASSPHRASE_ENV_VAR} is not set"))?,
+        PassphraseSource::FileDescriptor(fd) => {
+            let input = read_passphrase_from_fd(fd)?;
+            input.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
     Ok(SecretString::from(input))
 }
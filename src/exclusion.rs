@@ -0,0 +1,43 @@
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use regex::RegexSet;
+
+use crate::configuration::ExclusionConfiguration;
+
+/// Decides which windows must not be recorded.
+pub struct ExclusionRules {
+    /// Lowercase application names.
+    applications: Vec<String>,
+    window_titles: RegexSet,
+    /// Lowercase private browsing markers.
+    private_browsing_markers: Vec<String>,
+}
+
+impl ExclusionRules {
+    pub fn new(configuration: &ExclusionConfiguration) -> Result<Self> {
+        let window_titles = RegexSet::new(&configuration.window_titles)
+            .wrap_err("invalid window title pattern in exclusions")?;
+
+        Ok(Self {
+            applications: lowercase(&configuration.applications),
+            window_titles,
+            private_browsing_markers: lowercase(&configuration.private_browsing_markers),
+        })
+    }
+
+    pub fn is_excluded(&self, application_name: &str, window_title: &str) -> bool {
+        let application_name = application_name.to_lowercase();
+        let lowercase_title = window_title.to_lowercase();
+
+        self.applications.contains(&application_name)
+            || self.window_titles.is_match(window_title)
+            || self
+                .private_browsing_markers
+                .iter()
+                .any(|marker| lowercase_title.contains(marker))
+    }
+}
+
+fn lowercase(values: &[String]) -> Vec<String> {
+    values.iter().map(|value| value.to_lowercase()).collect()
+}
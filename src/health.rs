@@ -1,4 +1,5 @@
-use sysinfo::System;
+use camino::Utf8Path;
+use sysinfo::{Disks, System};
 use tokio::sync::Mutex;
 use tracing::info;
 
@@ -34,3 +35,15 @@ impl SystemHealth {
         average_cpu_load < CPU_THRESHOLD && free_memory > MEM_THRESHOLD
     }
 }
+
+/// Free space on the disk that contains `path`, if the disk can be found.
+pub fn free_disk_space(path: &Utf8Path) -> Option<u64> {
+    let path = path.canonicalize().ok()?;
+    let disks = Disks::new_with_refreshed_list();
+    // mount points can be nested, the disk with the longest matching mount point holds the path
+    disks
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
@@ -1,3 +1,116 @@
-pub fn compute_embeddings(text: &str) -> Vec<f32> {
-    todo!()
+use camino::Utf8Path;
+use color_eyre::eyre::{bail, eyre, Context};
+use color_eyre::Result;
+use rten::{InputOrOutput, Model, NodeId};
+use rten_tensor::prelude::*;
+use rten_tensor::NdTensor;
+use rten_text::tokenizers::{EncodeOptions, Tokenizer};
+
+use super::load_model;
+use crate::database::Screenshot;
+
+/// Name stored alongside each embedding, so vectors from different models are never compared.
+pub const MODEL_NAME: &str = "all-MiniLM-L6-v2";
+
+/// Longest token sequence the model accepts, longer inputs are truncated.
+const MAX_SEQUENCE_LENGTH: usize = 512;
+
+/// Sentence embedding model, e.g. all-MiniLM-L6-v2 converted to `.rten` with `rten-convert`.
+pub struct TextEmbedder {
+    model: Model,
+    tokenizer: Tokenizer,
+}
+
+impl TextEmbedder {
+    pub fn load(model: &Utf8Path, tokenizer: &Utf8Path) -> Result<Self> {
+        let model = load_model(model)?;
+        let tokenizer_json = std::fs::read_to_string(tokenizer)
+            .wrap_err_with(|| format!("unable to read tokenizer {tokenizer}"))?;
+        let tokenizer = Tokenizer::from_json(&tokenizer_json)
+            .map_err(|e| eyre!("Failed to load tokenizer: {}", e))?;
+
+        Ok(Self { model, tokenizer })
+    }
+
+    /// Computes a normalized embedding for `text` by mean-pooling the model's last hidden state.
+    /// This is CPU-bound and should be run on a blocking thread.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let encoded = self
+            .tokenizer
+            .encode(text.into(), EncodeOptions::default())
+            .map_err(|e| eyre!("Failed to tokenize text: {}", e))?;
+        let token_ids: Vec<i32> = encoded
+            .token_ids()
+            .iter()
+            .take(MAX_SEQUENCE_LENGTH)
+            .map(|id| *id as i32)
+            .collect();
+        if token_ids.is_empty() {
+            bail!("cannot embed empty text");
+        }
+
+        let sequence_length = token_ids.len();
+        let input_ids = NdTensor::from_data([1, sequence_length], token_ids);
+        let attention_mask = NdTensor::full([1, sequence_length], 1i32);
+        let token_type_ids = NdTensor::<i32, 2>::zeros([1, sequence_length]);
+
+        let mut inputs: Vec<(NodeId, InputOrOutput)> = vec![
+            (self.model.node_id("input_ids")?, input_ids.view().into()),
+            (
+                self.model.node_id("attention_mask")?,
+                attention_mask.view().into(),
+            ),
+        ];
+        if let Some(token_type_ids_id) = self.model.find_node("token_type_ids") {
+            inputs.push((token_type_ids_id, token_type_ids.view().into()));
+        }
+
+        let output_id = self.model.node_id("last_hidden_state")?;
+        let [last_hidden_state] = self.model.run_n(inputs, [output_id], None)?;
+        let last_hidden_state: NdTensor<f32, 3> = last_hidden_state.try_into()?;
+
+        let [_, tokens, dimensions] = last_hidden_state.shape();
+        let mut embedding = vec![0.0; dimensions];
+        for token in 0..tokens {
+            for (dimension, value) in embedding.iter_mut().enumerate() {
+                *value += last_hidden_state[[0, token, dimension]];
+            }
+        }
+
+        let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|v| *v /= norm);
+        }
+
+        Ok(embedding)
+    }
+}
+
+/// The text that gets embedded for a screenshot: its description followed by its OCR text.
+pub fn embedding_text(screenshot: &Screenshot) -> Option<String> {
+    let parts: Vec<_> = [&screenshot.description, &screenshot.text_content]
+        .into_iter()
+        .flatten()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("\n\n"))
+    }
+}
+
+/// Packs an embedding into little-endian bytes for storage in a BLOB column.
+pub fn to_bytes(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Unpacks an embedding stored with [`to_bytes`].
+pub fn from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
 }
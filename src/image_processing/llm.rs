@@ -1,38 +1,583 @@
 use std::env::consts::OS;
 
 use age::secrecy::SecretString;
+use async_trait::async_trait;
+use color_eyre::eyre::{bail, eyre, OptionExt};
 use color_eyre::Result;
 use ollama_rs::generation::completion::request::GenerationRequest;
 use ollama_rs::generation::images::Image;
+use ollama_rs::generation::options::GenerationOptions;
+use ollama_rs::generation::parameters::FormatType;
 use ollama_rs::Ollama;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+use crate::configuration::{LlmBackendType, LlmConfiguration};
 use crate::database::Screenshot;
 use crate::encryption;
 
-const MODEL_NAME: &str = "llava-llama3";
+/// Longest OCR text that is included in a prompt.
+const MAX_OCR_TEXT_CHARS: usize = 4000;
+
+/// Appended to every prompt, so the answer can be parsed into a [`Description`].
+const OUTPUT_INSTRUCTIONS: &str = r#"Answer with a JSON object with these fields:
+- "summary": one or two sentences describing what the user is doing
+- "activity": one of "coding", "reading", "writing", "meeting", "chat", "browsing", "media", "gaming" or "other"
+- "project": name of the project the user is working on, or null if unclear
+- "people": names of people that appear on the screen
+- "urls": URLs that are visible on the screen
+- "keywords": keywords describing the contents of the screenshot"#;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Activity {
+    Coding,
+    Reading,
+    Writing,
+    Meeting,
+    Chat,
+    Browsing,
+    Media,
+    Gaming,
+    #[default]
+    #[serde(other)]
+    Other,
+}
+
+/// Structured description of a screenshot, as returned by the LLM. Models often answer
+/// `null` instead of leaving out fields they have nothing for, so that is accepted as well.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Description {
+    pub summary: String,
+
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub activity: Activity,
+
+    #[serde(default)]
+    pub project: Option<String>,
+
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub people: Vec<String>,
+
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub urls: Vec<String>,
+
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub keywords: Vec<String>,
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+fn clean_list(values: Vec<String>) -> Vec<String> {
+    let mut cleaned: Vec<String> = vec![];
+    for value in values {
+        let value = value.trim();
+        if !value.is_empty() && !cleaned.iter().any(|v| v.eq_ignore_ascii_case(value)) {
+            cleaned.push(value.to_string());
+        }
+    }
+    cleaned
+}
+
+impl Description {
+    /// Parses and validates the answer of the model. Models sometimes wrap JSON in a
+    /// Markdown code block even when asked not to, so that is stripped first.
+    pub fn parse(answer: &str) -> Result<Self> {
+        let json = answer
+            .trim()
+            .trim_start_matches("```json")
+            .trim_start_matches("```")
+            .trim_end_matches("```");
+        let description: Description = serde_json::from_str(json)
+            .map_err(|e| eyre!("LLM answer is not a valid description: {e}\n{answer}"))?;
+
+        let summary = description.summary.trim().to_string();
+        if summary.is_empty() {
+            bail!("LLM answer has an empty summary");
+        }
+
+        Ok(Self {
+            summary,
+            activity: description.activity,
+            project: description
+                .project
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty()),
+            people: clean_list(description.people),
+            urls: clean_list(description.urls)
+                .into_iter()
+                .filter(|url| url.contains('.') && !url.contains(char::is_whitespace))
+                .collect(),
+            keywords: clean_list(description.keywords),
+        })
+    }
+
+    /// Plain text version of the description, stored in the `description` column so it is
+    /// part of full-text search.
+    pub fn to_text(&self) -> String {
+        if self.keywords.is_empty() {
+            self.summary.clone()
+        } else {
+            format!("{}\n{}", self.summary, self.keywords.join(", "))
+        }
+    }
+}
+
+/// A vision model that can answer prompts about images.
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    /// Sends `prompt` along with a base64-encoded PNG image and returns the model's answer,
+    /// which is constrained to be a JSON object.
+    async fn complete(&self, prompt: String, image_base64: String) -> Result<String>;
+}
+
+pub fn backend(configuration: &LlmConfiguration) -> Box<dyn LlmBackend> {
+    match configuration.backend {
+        LlmBackendType::Ollama => Box::new(OllamaBackend::new(configuration.clone())),
+        LlmBackendType::OpenAi => Box::new(OpenAiBackend::new(configuration.clone())),
+    }
+}
+
+pub struct OllamaBackend {
+    ollama: Ollama,
+    configuration: LlmConfiguration,
+}
+
+impl OllamaBackend {
+    pub fn new(configuration: LlmConfiguration) -> Self {
+        Self {
+            ollama: Ollama::new(configuration.host.clone(), configuration.port),
+            configuration,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmBackend for OllamaBackend {
+    async fn complete(&self, prompt: String, image_base64: String) -> Result<String> {
+        let mut options = GenerationOptions::default();
+        if let Some(temperature) = self.configuration.temperature {
+            options = options.temperature(temperature);
+        }
+        if let Some(context_size) = self.configuration.context_size {
+            options = options.num_ctx(context_size);
+        }
+        let request = GenerationRequest::new(self.configuration.model.clone(), prompt)
+            .images(vec![Image::from_base64(&image_base64)])
+            .options(options)
+            .format(FormatType::Json);
+
+        let response = self.ollama.generate(request).await?;
+        Ok(response.response)
+    }
+}
+
+/// Backend for servers that implement OpenAI's `/v1/chat/completions` with image inputs.
+pub struct OpenAiBackend {
+    client: reqwest::Client,
+    configuration: LlmConfiguration,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletion {
+    choices: Vec<ChatCompletionChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChoice {
+    message: ChatCompletionMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionMessage {
+    content: Option<String>,
+}
+
+impl OpenAiBackend {
+    pub fn new(configuration: LlmConfiguration) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            configuration,
+        }
+    }
+
+    fn url(&self) -> String {
+        format!(
+            "{}:{}/v1/chat/completions",
+            self.configuration.host.trim_end_matches('/'),
+            self.configuration.port
+        )
+    }
+}
+
+#[async_trait]
+impl LlmBackend for OpenAiBackend {
+    async fn complete(&self, prompt: String, image_base64: String) -> Result<String> {
+        let body = json!({
+            "model": self.configuration.model,
+            "temperature": self.configuration.temperature,
+            "response_format": { "type": "json_object" },
+            "messages": [{
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": prompt },
+                    {
+                        "type": "image_url",
+                        "image_url": { "url": format!("data:image/png;base64,{image_base64}") }
+                    }
+                ]
+            }]
+        });
+
+        let mut request = self.client.post(self.url()).json(&body);
+        if let Some(api_key) = &self.configuration.api_key {
+            request = request.bearer_auth(api_key.expose());
+        }
+        let completion: ChatCompletion = request.send().await?.error_for_status()?.json().await?;
+
+        completion
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_eyre("LLM response contains no message")
+    }
+}
+
+fn platform() -> &'static str {
+    match OS {
+        "macos" => "MacOS",
+        "windows" => "Windows",
+        "linux" => "Linux",
+        _ => "Unknown",
+    }
+}
+
+/// Truncates OCR text to at most `max_chars` characters, so screens full of text don't
+/// push the image and instructions out of the model's context window.
+fn truncate_text(text: &str, max_chars: usize) -> &str {
+    match text.char_indices().nth(max_chars) {
+        Some((index, _)) => &text[..index],
+        None => text,
+    }
+}
+
+/// Fills in the variables of a prompt template, see [`LlmConfiguration::prompt`].
+fn render_prompt(template: &str, screenshot: &Screenshot) -> String {
+    let ocr_text = screenshot
+        .text_content
+        .as_deref()
+        .map(|text| truncate_text(text.trim(), MAX_OCR_TEXT_CHARS))
+        .filter(|text| !text.is_empty())
+        .unwrap_or("(no text recognized)");
+
+    // in a single pass, so placeholders in window titles or recognized text stay as they are
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let value = match &rest[1..end] {
+                "platform" => platform(),
+                "app" => screenshot.application_name.as_str(),
+                "title" => screenshot.window_title.as_str(),
+                "ocr_text" => ocr_text,
+                _ => return None,
+            };
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
 
 pub async fn generate_description(
+    backend: &dyn LlmBackend,
     screenshot: &Screenshot,
     passphrase: &SecretString,
-) -> Result<String> {
+    configuration: &LlmConfiguration,
+) -> Result<Description> {
     use base64::engine::general_purpose::STANDARD;
     use base64::Engine as _;
 
-    let ollama = Ollama::default();
-    let bytes = encryption::decrypt_file(&screenshot.path, &passphrase).await?;
+    let bytes = encryption::decrypt_file(&screenshot.path, passphrase).await?;
     let base64 = STANDARD.encode(bytes);
-    let platform = match OS {
-        "macos" => "MacOS",
-        "windows" => "Windows",
-        "linux" => "Linux",
-        _ => "Unknown",
-    };
     let prompt = format!(
-        "The following image is a screenshot from a {platform} computer. Describe its contents using keywords, separated by comams."
+        "{}\n\n{OUTPUT_INSTRUCTIONS}",
+        render_prompt(&configuration.prompt, screenshot)
     );
-    let request =
-        GenerationRequest::new(MODEL_NAME.into(), prompt).images(vec![Image::from_base64(&base64)]);
 
-    let response = ollama.generate(request).await?;
-    Ok(response.response)
+    let answer = tokio::time::timeout(configuration.timeout, backend.complete(prompt, base64))
+        .await
+        .map_err(|_| {
+            eyre!(
+                "LLM did not respond within {} seconds",
+                configuration.timeout.as_secs()
+            )
+        })??;
+    Description::parse(&answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use camino::{Utf8Path, Utf8PathBuf};
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::database::ProcessingStatus;
+
+    const ANSWER: &str = r#"{"summary": "Editing a Rust file", "activity": "coding", "project": "reminisce", "people": [], "urls": [], "keywords": ["rust"]}"#;
+
+    /// Authorization header of the last request the mock server received.
+    type Authorization = Arc<Mutex<Option<String>>>;
+
+    /// Starts a local server that answers every POST to `path` with `status` and `body`, and
+    /// returns its port.
+    async fn mock_server(
+        path: &'static str,
+        status: StatusCode,
+        body: String,
+    ) -> (u16, Authorization) {
+        let authorization = Authorization::default();
+        let received = authorization.clone();
+        let app = Router::new().route(
+            path,
+            post(move |headers: HeaderMap| async move {
+                *received.lock().unwrap() = headers
+                    .get("authorization")
+                    .map(|value| value.to_str().unwrap().to_string());
+                (status, body)
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (port, authorization)
+    }
+
+    fn configuration(backend: LlmBackendType, port: u16) -> LlmConfiguration {
+        LlmConfiguration {
+            backend,
+            host: "http://127.0.0.1".to_string(),
+            port,
+            api_key: serde_json::from_str(r#""test-key""#).unwrap(),
+            ..LlmConfiguration::default()
+        }
+    }
+
+    /// Writes an encrypted image and returns a screenshot that refers to it.
+    async fn screenshot(passphrase: &SecretString) -> Screenshot {
+        let directory = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("reminisce-llm-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("image.png.enc");
+        encryption::encrypt_file(&path, passphrase.clone(), b"not really a png".to_vec())
+            .await
+            .unwrap();
+
+        Screenshot {
+            id: 1,
+            timestamp: OffsetDateTime::now_utc(),
+            path: path.to_string(),
+            description: None,
+            text_content: Some("fn main() {}".to_string()),
+            status: ProcessingStatus::Pending,
+            window_title: "main.rs".to_string(),
+            application_name: "Code".to_string(),
+        }
+    }
+
+    async fn describe(
+        backend_type: LlmBackendType,
+        path: &'static str,
+        status: StatusCode,
+        body: String,
+    ) -> Result<Description> {
+        let (port, authorization) = mock_server(path, status, body).await;
+        let configuration = configuration(backend_type, port);
+        let passphrase = SecretString::from("passphrase".to_string());
+        let screenshot = screenshot(&passphrase).await;
+        let result = generate_description(
+            backend(&configuration).as_ref(),
+            &screenshot,
+            &passphrase,
+            &configuration,
+        )
+        .await;
+        std::fs::remove_dir_all(Utf8Path::new(&screenshot.path).parent().unwrap()).unwrap();
+
+        // only the OpenAI backend sends the key
+        let expected = match backend_type {
+            LlmBackendType::OpenAi => Some("Bearer test-key".to_string()),
+            LlmBackendType::Ollama => None,
+        };
+        assert_eq!(*authorization.lock().unwrap(), expected);
+        result
+    }
+
+    fn ollama_body(answer: &str) -> String {
+        json!({
+            "model": "llava-llama3",
+            "created_at": "2026-10-17T12:00:00Z",
+            "response": answer,
+            "done": true
+        })
+        .to_string()
+    }
+
+    fn openai_body(answer: &str) -> String {
+        json!({ "choices": [{ "message": { "role": "assistant", "content": answer } }] })
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn ollama_description() {
+        let description = describe(
+            LlmBackendType::Ollama,
+            "/api/generate",
+            StatusCode::OK,
+            ollama_body(ANSWER),
+        )
+        .await
+        .unwrap();
+        assert_eq!(description.summary, "Editing a Rust file");
+        assert_eq!(description.activity, Activity::Coding);
+        assert_eq!(description.keywords, ["rust"]);
+    }
+
+    #[tokio::test]
+    async fn ollama_error_status() {
+        let result = describe(
+            LlmBackendType::Ollama,
+            "/api/generate",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({ "error": "model not found" }).to_string(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn ollama_malformed_body() {
+        let result = describe(
+            LlmBackendType::Ollama,
+            "/api/generate",
+            StatusCode::OK,
+            "{ not json".to_string(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn openai_description() {
+        let description = describe(
+            LlmBackendType::OpenAi,
+            "/v1/chat/completions",
+            StatusCode::OK,
+            openai_body(ANSWER),
+        )
+        .await
+        .unwrap();
+        assert_eq!(description.summary, "Editing a Rust file");
+        assert_eq!(description.project.as_deref(), Some("reminisce"));
+    }
+
+    #[tokio::test]
+    async fn openai_error_status() {
+        let result = describe(
+            LlmBackendType::OpenAi,
+            "/v1/chat/completions",
+            StatusCode::UNAUTHORIZED,
+            json!({ "error": { "message": "invalid api key" } }).to_string(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn openai_malformed_body() {
+        let result = describe(
+            LlmBackendType::OpenAi,
+            "/v1/chat/completions",
+            StatusCode::OK,
+            json!({ "unexpected": true }).to_string(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn answer_that_is_not_a_description() {
+        let result = describe(
+            LlmBackendType::OpenAi,
+            "/v1/chat/completions",
+            StatusCode::OK,
+            openai_body("I can't see the screen"),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn prompt_variables_are_filled_in_once() {
+        let screenshot = Screenshot {
+            id: 1,
+            timestamp: OffsetDateTime::UNIX_EPOCH,
+            path: String::new(),
+            description: None,
+            text_content: Some("fn main() { println!(\"{title}\") }".into()),
+            status: ProcessingStatus::Pending,
+            window_title: "{ocr_text}".into(),
+            application_name: "Editor".into(),
+        };
+        assert_eq!(
+            render_prompt("{app} {title} {unknown} {ocr_text}", &screenshot),
+            "Editor {ocr_text} {unknown} fn main() { println!(\"{title}\") }"
+        );
+    }
+
+    #[test]
+    fn null_fields() {
+        let description = Description::parse(
+            r#"{"summary": "Reading", "activity": null, "project": null, "people": null,
+                "urls": null, "keywords": null}"#,
+        )
+        .unwrap();
+        assert_eq!(description.activity, Activity::Other);
+        assert_eq!(description.project, None);
+        assert!(description.people.is_empty());
+        assert!(description.urls.is_empty());
+        assert!(description.keywords.is_empty());
+    }
+
+    #[test]
+    fn api_key_is_not_logged() {
+        let configuration = configuration(LlmBackendType::OpenAi, 8080);
+        assert!(!format!("{configuration:?}").contains("test-key"));
+    }
 }
@@ -1,4 +1,20 @@
+use camino::Utf8Path;
+use color_eyre::eyre::{bail, Context};
+use color_eyre::Result;
+use rten::Model;
+
 pub mod embeddings;
 pub mod llm;
 pub mod ocr;
 pub mod similarity;
+
+/// Loads an `.rten` model, with a hint on how to get it if the file doesn't exist.
+pub fn load_model(path: &Utf8Path) -> Result<Model> {
+    if !path.is_file() {
+        bail!(
+            "model {path} not found, download the models with `just download-models` or configure their paths in reminisce.json"
+        );
+    }
+
+    Model::load_file(path).wrap_err_with(|| format!("unable to load model {path}"))
+}
@@ -1,26 +1,95 @@
-use age::secrecy::SecretString;
+use camino::Utf8Path;
 use color_eyre::eyre::eyre;
 use color_eyre::Result;
-use ocrs::{ImageSource, OcrEngine, OcrEngineParams};
-use rten::Model;
-
-use crate::database::Screenshot;
-
-pub async fn extract_text(screenshot: &Screenshot, passphrase: &SecretString) -> Result<String> {
-    let params = OcrEngineParams {
-        detection_model: Some(Model::load_file("models/text-detection.rten")?),
-        recognition_model: Some(Model::load_file("models/text-recognition.rten")?),
-        ..Default::default()
-    };
-
-    let engine = OcrEngine::new(params).map_err(|e| eyre!("Failed to create engine: {}", e))?;
-    let image = screenshot.load_image(passphrase).await?;
-    let img_source = ImageSource::from_bytes(image.as_raw(), image.dimensions())?;
-    let input = engine
-        .prepare_input(img_source)
-        .map_err(|e| eyre!("Failed to prepare input: {}", e))?;
-    let text = engine
-        .get_text(&input)
-        .map_err(|e| eyre!("Failed to get text: {}", e))?;
-    Ok(text)
+use image::RgbImage;
+use ocrs::{ImageSource, OcrEngine, OcrEngineParams, TextItem};
+use rten_imageproc::Rect;
+
+use super::load_model;
+use crate::database::TextBlock;
+
+/// Text found on an image.
+pub struct OcrResult {
+    /// All recognized lines, separated by newlines.
+    pub text: String,
+
+    /// Position of every recognized line and word.
+    pub blocks: Vec<TextBlock>,
+}
+
+fn text_block(line: usize, word: Option<usize>, text: String, rect: Rect) -> TextBlock {
+    TextBlock {
+        line: line as i64,
+        word: word.map(|w| w as i64),
+        text,
+        x: rect.left().into(),
+        y: rect.top().into(),
+        width: rect.width().into(),
+        height: rect.height().into(),
+        // ocrs doesn't report a recognition confidence
+        confidence: None,
+    }
+}
+
+/// OCR engine with its models loaded, meant to be created once and shared.
+pub struct TextRecognizer {
+    engine: OcrEngine,
+}
+
+impl TextRecognizer {
+    pub fn load(detection_model: &Utf8Path, recognition_model: &Utf8Path) -> Result<Self> {
+        let params = OcrEngineParams {
+            detection_model: Some(load_model(detection_model)?),
+            recognition_model: Some(load_model(recognition_model)?),
+            ..Default::default()
+        };
+
+        let engine = OcrEngine::new(params).map_err(|e| eyre!("Failed to create engine: {}", e))?;
+        Ok(Self { engine })
+    }
+
+    /// Extracts all text from an image, along with the position of each line and word.
+    /// This is CPU-bound and should be run on a blocking thread.
+    pub fn extract_text(&self, image: &RgbImage) -> Result<OcrResult> {
+        let img_source = ImageSource::from_bytes(image.as_raw(), image.dimensions())?;
+        let input = self
+            .engine
+            .prepare_input(img_source)
+            .map_err(|e| eyre!("Failed to prepare input: {}", e))?;
+        let word_rects = self
+            .engine
+            .detect_words(&input)
+            .map_err(|e| eyre!("Failed to detect words: {}", e))?;
+        let line_rects = self.engine.find_text_lines(&input, &word_rects);
+        let lines = self
+            .engine
+            .recognize_text(&input, &line_rects)
+            .map_err(|e| eyre!("Failed to recognize text: {}", e))?;
+
+        let mut text = vec![];
+        let mut blocks = vec![];
+        for (line_index, line) in lines.iter().flatten().enumerate() {
+            let line_text = line.to_string();
+            for (word_index, word) in line.words().enumerate() {
+                blocks.push(text_block(
+                    line_index,
+                    Some(word_index),
+                    word.to_string(),
+                    word.bounding_rect(),
+                ));
+            }
+            blocks.push(text_block(
+                line_index,
+                None,
+                line_text.clone(),
+                line.bounding_rect(),
+            ));
+            text.push(line_text);
+        }
+
+        Ok(OcrResult {
+            text: text.join("\n"),
+            blocks,
+        })
+    }
 }
@@ -0,0 +1,70 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Seek, Write};
+
+use camino::Utf8Path;
+use color_eyre::eyre::{bail, Context};
+use color_eyre::Result;
+use tracing::debug;
+
+/// File in the screenshot directory that is locked while a recorder runs.
+const LOCK_FILE_NAME: &str = ".lock";
+
+/// Advisory lock that makes sure only one recorder writes to a data directory, commands that
+/// delete screenshots without a recorder hold it as well. The operating system releases it
+/// when the process exits, even if it crashes, so it never goes stale.
+#[derive(Debug)]
+pub struct InstanceLock {
+    // the lock is held as long as the file is open
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Takes the lock, or fails with a message that names the process holding it.
+    pub fn acquire(screenshot_directory: &Utf8Path) -> Result<Self> {
+        match Self::try_acquire(screenshot_directory)? {
+            Some(lock) => Ok(lock),
+            None => {
+                let path = screenshot_directory.join(LOCK_FILE_NAME);
+                let pid = std::fs::read_to_string(&path).unwrap_or_default();
+                let pid = pid.trim();
+                // besides a recorder, this can be a `delete` or `prune` that is still running
+                if pid.is_empty() {
+                    bail!("another reminisce process is already using {screenshot_directory}");
+                }
+                bail!(
+                    "another reminisce process ({pid}) is already using {screenshot_directory}, \
+                     if it is a recorder, stop it with `reminisce stop` first"
+                );
+            }
+        }
+    }
+
+    /// Takes the lock, or returns `None` if another process holds it.
+    pub fn try_acquire(screenshot_directory: &Utf8Path) -> Result<Option<Self>> {
+        let path = screenshot_directory.join(LOCK_FILE_NAME);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .wrap_err_with(|| format!("unable to open {path}"))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(e)) => {
+                return Err(e).wrap_err_with(|| format!("unable to lock {path}"))
+            }
+        }
+
+        // only informational, the lock itself is what counts
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+        debug!("acquired {path}");
+
+        Ok(Some(Self { _file: file }))
+    }
+}
@@ -1,149 +1,168 @@
-use age::secrecy::SecretString;
-use camino::Utf8PathBuf;
-use color_eyre::eyre::bail;
+use clap::Parser;
+use cli::{Cli, Command, ConfigCommand};
 use color_eyre::Result;
-use configuration::Configuration;
+use configuration::ConfigurationSource;
+use control::ControlRequest;
 use database::Database;
-use queue::WorkQueue;
-use recorder::ScreenRecorder;
 use tracing::info;
 use tracing_subscriber::fmt::format::FmtSpan;
 
+mod capture;
+mod cli;
+mod commands;
 mod configuration;
+mod control;
 mod database;
 mod encryption;
+mod exclusion;
 mod health;
 mod image_processing;
+mod lock;
+mod mcp;
+mod pause;
 mod queue;
 mod recorder;
-
-async fn confirm_or_create_passphrase(
-    database: &Database,
-    config: &Configuration,
-) -> Result<SecretString> {
-    let screenshot_count = database.count().await?;
-    let test_file_path = config.screenshot_directory.join(".test");
-    if screenshot_count == 0 {
-        let passphrase = encryption::get_passphrase("Please create a passphrase: ")?;
-        encryption::encrypt_file(
-            test_file_path,
-            passphrase.clone(),
-            "data".as_bytes().to_vec(),
-        )
-        .await?;
-
-        Ok(passphrase)
-    } else {
-        let passphrase = encryption::get_passphrase("Please enter your passphrase: ")?;
-        let result = encryption::decrypt_file(&test_file_path, &passphrase).await;
-        if result.is_err() {
-            bail!("Wrong passphrase")
-        } else {
-            Ok(passphrase)
-        }
-    }
-}
-
-async fn start_recorder(
-    database: Database,
-    passphrase: SecretString,
-    configuration: Configuration,
-) -> Result<()> {
-    let mut work_queue =
-        WorkQueue::new(database.clone(), passphrase.clone(), configuration.clone());
-    let sender = work_queue.sender();
-    let screen_recorder = ScreenRecorder::new(
-        database,
-        configuration.screenshot_interval,
-        sender,
-        passphrase,
-        configuration,
-    )
-    .await?;
-
-    tokio::spawn(async move {
-        screen_recorder
-            .start()
-            .await
-            .expect("failed to start screen recording");
-    });
-
-    work_queue.start().await?;
-
-    Ok(())
-}
-
-async fn decrypt_screenshots(
-    database: Database,
-    passphrase: SecretString,
-    configuration: Configuration,
-) -> Result<()> {
-    let screenshots = database.find_all().await?;
-    for screenshot in screenshots {
-        info!("decrypting screenshot {}", screenshot.id);
-        let bytes = encryption::decrypt_file(&screenshot.path, &passphrase).await?;
-        let path = configuration
-            .screenshot_directory
-            .join(format!("{}.png", screenshot.id));
-        tokio::fs::write(path, bytes).await?;
-    }
-
-    Ok(())
-}
-
-async fn delete_everything(database: Database, configuration: Configuration) -> Result<()> {
-    use std::fs;
-
-    database.delete_all().await?;
-    let files = fs::read_dir(configuration.screenshot_directory)?;
-    for file in files {
-        let file = file?;
-        let path = Utf8PathBuf::try_from(file.path())?;
-        let should_delete = path
-            .file_name()
-            .map(|f| f.ends_with(".enc"))
-            .unwrap_or(false)
-            && file.file_type()?.is_file();
-
-        if should_delete {
-            fs::remove_file(path)?;
-        }
-    }
-
-    Ok(())
-}
+mod retention;
+mod server;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     use std::env;
 
     color_eyre::install()?;
-    dotenvy::dotenv()?;
+    let cli = Cli::parse();
+    dotenvy::dotenv().ok();
     let use_tokio_console = env::var("USE_TOKIO_CONSOLE").is_ok();
 
     if use_tokio_console {
         console_subscriber::init();
     } else {
+        // stdout carries the protocol messages of the MCP server
+        let log_to_stderr = matches!(cli.command, Some(Command::Mcp));
         tracing_subscriber::fmt()
             .compact()
             .with_span_events(FmtSpan::CLOSE)
+            .with_writer(move || -> Box<dyn std::io::Write> {
+                if log_to_stderr {
+                    Box::new(std::io::stderr())
+                } else {
+                    Box::new(std::io::stdout())
+                }
+            })
             .init();
     }
 
-    let configuration = configuration::load()?;
+    if let Some(data_directory) = &cli.data_dir {
+        std::fs::create_dir_all(data_directory.join("screenshots"))?;
+    }
+    let configuration_source = ConfigurationSource {
+        path: cli.config.clone(),
+        data_directory: cli.data_dir.clone(),
+    };
+    let configuration = configuration_source.load()?;
     info!("starting up, using configuration {configuration:?}");
 
+    let passphrase_source = cli.passphrase_source();
+    let command = cli.command.unwrap_or(Command::Record { replay: None });
+
+    // these don't need the database, which might not exist yet
+    match command {
+        Command::Config(ConfigCommand::Check) => {
+            return commands::check_config(&configuration, &cli.config)
+        }
+        Command::Doctor => return commands::doctor(configuration).await,
+        Command::Pause { duration } => return commands::pause(configuration, duration).await,
+        Command::Resume => return commands::resume(configuration).await,
+        Command::Status => return commands::status(configuration).await,
+        Command::Capture => {
+            return commands::control(configuration, ControlRequest::CaptureNow).await
+        }
+        Command::Reload => {
+            return commands::control(configuration, ControlRequest::ReloadConfig).await
+        }
+        Command::Stop => return commands::control(configuration, ControlRequest::Shutdown).await,
+        // a running recorder does these, so they open the database only if none is running
+        Command::Delete(arguments) => {
+            return commands::delete(configuration, passphrase_source, arguments).await
+        }
+        Command::Prune { dry_run } => return commands::prune(configuration, dry_run).await,
+        _ => {}
+    }
+
     let database = Database::new(&configuration.database_file_name).await?;
-    let passphrase = confirm_or_create_passphrase(&database, &configuration).await?;
 
-    let argument = env::args().nth(1);
-    match argument.as_deref() {
-        Some("record") => start_recorder(database, passphrase, configuration).await?,
-        Some("decrypt") => decrypt_screenshots(database, passphrase, configuration).await?,
-        Some("delete") => delete_everything(database, configuration).await?,
-        _ => {
-            start_recorder(database, passphrase, configuration).await?;
+    match command {
+        Command::Record { replay } => {
+            commands::record(
+                database,
+                configuration,
+                configuration_source,
+                passphrase_source,
+                replay,
+            )
+            .await?
+        }
+        Command::Search(arguments) => commands::search(database, configuration, arguments).await?,
+        Command::Show { id } => commands::show(database, id).await?,
+        Command::Export {
+            output,
+            images,
+            range,
+        } => {
+            commands::export(
+                database,
+                configuration,
+                passphrase_source,
+                output,
+                images,
+                range,
+            )
+            .await?
+        }
+        Command::Serve { port } => {
+            let passphrase = commands::confirm_or_create_passphrase(
+                &database,
+                &configuration,
+                passphrase_source,
+            )
+            .await?;
+            server::serve(
+                database,
+                passphrase,
+                &configuration.screenshot_directory,
+                port,
+            )
+            .await?
+        }
+        Command::Mcp => {
+            let passphrase = commands::confirm_or_create_passphrase(
+                &database,
+                &configuration,
+                passphrase_source,
+            )
+            .await?;
+            mcp::serve(database, passphrase).await?
+        }
+        Command::Decrypt => {
+            let passphrase = commands::confirm_or_create_passphrase(
+                &database,
+                &configuration,
+                passphrase_source,
+            )
+            .await?;
+            commands::decrypt_screenshots(database, passphrase, configuration).await?
         }
+        Command::Stats => commands::stats(database).await?,
+        Command::Config(_)
+        | Command::Doctor
+        | Command::Pause { .. }
+        | Command::Resume
+        | Command::Status
+        | Command::Capture
+        | Command::Reload
+        | Command::Stop
+        | Command::Delete(_)
+        | Command::Prune { .. } => unreachable!("handled above"),
     }
 
     Ok(())
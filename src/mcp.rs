@@ -0,0 +1,388 @@
+//! Model Context Protocol server, so AI assistants can query the recorded history. Messages are
+//! JSON-RPC 2.0, one per line on stdin and stdout. Logs go to stderr.
+
+use age::secrecy::SecretString;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use color_eyre::eyre::bail;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tracing::{debug, info, warn};
+
+use crate::database::{Database, Screenshot, SearchFilters};
+use crate::encryption;
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Most search results returned to the client, to keep responses small.
+const MAX_SEARCH_RESULTS: i64 = 50;
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    /// Missing for notifications, which don't get a response.
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorObject {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorObject>,
+}
+
+impl Response {
+    fn result(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    fn error(id: Value, code: i64, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result: None,
+            error: Some(ErrorObject {
+                code,
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+fn tool_definitions() -> Value {
+    json!([
+        {
+            "name": "search_screenshots",
+            "description": "Full text search over the text on screenshots, their window titles and descriptions. Returns the best matches first.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "Words to search for" },
+                    "from": { "type": "string", "description": "Only screenshots taken at or after this RFC 3339 time" },
+                    "to": { "type": "string", "description": "Only screenshots taken at or before this RFC 3339 time" },
+                    "app": { "type": "string", "description": "Only screenshots of this application" },
+                    "limit": { "type": "integer", "description": "Maximum number of results, at most 50" }
+                },
+                "required": ["query"]
+            }
+        },
+        {
+            "name": "get_screenshot_text",
+            "description": "Returns the text recognized on a screenshot, its description and the window it was taken of.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "id": { "type": "integer", "description": "ID of the screenshot" }
+                },
+                "required": ["id"]
+            }
+        },
+        {
+            "name": "list_activity",
+            "description": "Summarizes what was on screen in a time range: the number of screenshots per activity, and which windows were used when.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "from": { "type": "string", "description": "Start of the range as RFC 3339 time" },
+                    "to": { "type": "string", "description": "End of the range as RFC 3339 time" }
+                },
+                "required": ["from", "to"]
+            }
+        },
+        {
+            "name": "get_screenshot_image",
+            "description": "Returns the screenshot image as PNG.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "id": { "type": "integer", "description": "ID of the screenshot" }
+                },
+                "required": ["id"]
+            }
+        }
+    ])
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchArguments {
+    query: String,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    to: Option<OffsetDateTime>,
+    app: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScreenshotArguments {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ActivityArguments {
+    #[serde(with = "time::serde::rfc3339")]
+    from: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    to: OffsetDateTime,
+}
+
+/// Consecutive screenshots of the same window.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WindowSpan {
+    #[serde(with = "time::serde::rfc3339")]
+    from: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    to: OffsetDateTime,
+    application_name: String,
+    window_title: String,
+    screenshots: usize,
+}
+
+fn window_spans(screenshots: Vec<Screenshot>) -> Vec<WindowSpan> {
+    let mut spans: Vec<WindowSpan> = vec![];
+    for screenshot in screenshots {
+        if let Some(span) = spans.last_mut() {
+            if span.application_name == screenshot.application_name
+                && span.window_title == screenshot.window_title
+            {
+                span.to = screenshot.timestamp;
+                span.screenshots += 1;
+                continue;
+            }
+        }
+        spans.push(WindowSpan {
+            from: screenshot.timestamp,
+            to: screenshot.timestamp,
+            application_name: screenshot.application_name,
+            window_title: screenshot.window_title,
+            screenshots: 1,
+        });
+    }
+    spans
+}
+
+fn text_content(text: impl Into<String>) -> Value {
+    json!({ "content": [{ "type": "text", "text": text.into() }] })
+}
+
+fn timestamp(time: OffsetDateTime) -> String {
+    time.format(&Rfc3339).unwrap_or_default()
+}
+
+struct McpServer {
+    database: Database,
+    passphrase: SecretString,
+}
+
+impl McpServer {
+    async fn search_screenshots(&self, arguments: SearchArguments) -> Result<Value> {
+        let filters = SearchFilters {
+            from: arguments.from,
+            to: arguments.to,
+            application_name: arguments.app,
+            limit: Some(
+                arguments
+                    .limit
+                    .unwrap_or(MAX_SEARCH_RESULTS)
+                    .clamp(1, MAX_SEARCH_RESULTS),
+            ),
+            ..SearchFilters::default()
+        };
+        let results = self.database.search(&arguments.query, &filters).await?;
+        if results.is_empty() {
+            return Ok(text_content(format!(
+                "No screenshots found for \"{}\".",
+                arguments.query
+            )));
+        }
+
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|result| {
+                json!({
+                    "id": result.screenshot.id,
+                    "timestamp": timestamp(result.screenshot.timestamp),
+                    "applicationName": result.screenshot.application_name,
+                    "windowTitle": result.screenshot.window_title,
+                    "snippet": result.snippet,
+                })
+            })
+            .collect();
+        Ok(text_content(serde_json::to_string_pretty(&results)?))
+    }
+
+    async fn get_screenshot_text(&self, arguments: ScreenshotArguments) -> Result<Value> {
+        let screenshot = self.database.find_by_id(arguments.id).await?;
+        let description = self
+            .database
+            .find_description(arguments.id)
+            .await?
+            .map(|description| description.to_text())
+            .or(screenshot.description);
+        let text = json!({
+            "id": screenshot.id,
+            "timestamp": timestamp(screenshot.timestamp),
+            "applicationName": screenshot.application_name,
+            "windowTitle": screenshot.window_title,
+            "description": description,
+            "text": screenshot.text_content,
+        });
+        Ok(text_content(serde_json::to_string_pretty(&text)?))
+    }
+
+    async fn list_activity(&self, arguments: ActivityArguments) -> Result<Value> {
+        if arguments.from > arguments.to {
+            bail!("from must not be after to");
+        }
+
+        let activities: Vec<_> = self
+            .database
+            .count_by_activity(Some(arguments.from), Some(arguments.to))
+            .await?
+            .into_iter()
+            .map(|(activity, screenshots)| {
+                json!({ "activity": activity, "screenshots": screenshots })
+            })
+            .collect();
+        let screenshots = self
+            .database
+            .find_by_time_range(Some(arguments.from), Some(arguments.to), None)
+            .await?;
+        let activity = json!({
+            "activities": activities,
+            "windows": window_spans(screenshots),
+        });
+        Ok(text_content(serde_json::to_string_pretty(&activity)?))
+    }
+
+    async fn get_screenshot_image(&self, arguments: ScreenshotArguments) -> Result<Value> {
+        let Some(path) = self.database.find_image_path(arguments.id).await? else {
+            bail!(
+                "the image of screenshot {} was deleted, only its text is kept",
+                arguments.id
+            );
+        };
+        let bytes = encryption::decrypt_file(&path, &self.passphrase).await?;
+        Ok(json!({
+            "content": [{
+                "type": "image",
+                "data": BASE64_STANDARD.encode(bytes),
+                "mimeType": "image/png",
+            }]
+        }))
+    }
+
+    async fn call_tool(&self, call: ToolCall) -> std::result::Result<Value, (i64, String)> {
+        fn arguments<T: for<'de> Deserialize<'de>>(
+            arguments: Value,
+        ) -> std::result::Result<T, (i64, String)> {
+            serde_json::from_value(arguments)
+                .map_err(|e| (INVALID_PARAMS, format!("invalid arguments: {e}")))
+        }
+
+        let result = match call.name.as_str() {
+            "search_screenshots" => self.search_screenshots(arguments(call.arguments)?).await,
+            "get_screenshot_text" => self.get_screenshot_text(arguments(call.arguments)?).await,
+            "list_activity" => self.list_activity(arguments(call.arguments)?).await,
+            "get_screenshot_image" => self.get_screenshot_image(arguments(call.arguments)?).await,
+            name => return Err((INVALID_PARAMS, format!("unknown tool {name}"))),
+        };
+
+        // failures of the tool itself are reported to the model instead of the client
+        Ok(result.unwrap_or_else(|e| {
+            warn!("tool {} failed: {e:#}", call.name);
+            json!({
+                "content": [{ "type": "text", "text": format!("{e:#}") }],
+                "isError": true,
+            })
+        }))
+    }
+
+    async fn handle(&self, request: Request) -> Option<Response> {
+        debug!("received {}", request.method);
+        let id = request.id?;
+        let result = match request.method.as_str() {
+            "initialize" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "reminisce", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+            "tools/call" => match serde_json::from_value(request.params) {
+                Ok(call) => self.call_tool(call).await,
+                Err(e) => Err((INVALID_PARAMS, format!("invalid tool call: {e}"))),
+            },
+            method => Err((METHOD_NOT_FOUND, format!("unknown method {method}"))),
+        };
+
+        Some(match result {
+            Ok(result) => Response::result(id, result),
+            Err((code, message)) => Response::error(id, code, message),
+        })
+    }
+}
+
+/// Answers requests on stdin until it is closed.
+pub async fn serve(database: Database, passphrase: SecretString) -> Result<()> {
+    let server = McpServer {
+        database,
+        passphrase,
+    };
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    info!("MCP server waiting for requests on stdin");
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => server.handle(request).await,
+            Err(e) => Some(Response::error(Value::Null, PARSE_ERROR, e.to_string())),
+        };
+        if let Some(response) = response {
+            let mut message = serde_json::to_vec(&response)?;
+            message.push(b'\n');
+            stdout.write_all(&message).await?;
+            stdout.flush().await?;
+        }
+    }
+
+    info!("stdin was closed, stopping MCP server");
+    Ok(())
+}
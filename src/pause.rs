@@ -0,0 +1,75 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+/// File in the screenshot directory that holds the pause, so that other processes can pause
+/// the recorder and pauses survive restarts.
+const PAUSE_FILE_NAME: &str = ".pause";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pause {
+    /// When recording resumes by itself, `None` pauses until resumed.
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub until: Option<OffsetDateTime>,
+}
+
+impl Pause {
+    fn is_expired(&self) -> bool {
+        self.until
+            .is_some_and(|until| until <= OffsetDateTime::now_utc())
+    }
+}
+
+/// Whether recording is paused. Processing continues while paused.
+#[derive(Debug, Clone)]
+pub struct PauseState {
+    path: Utf8PathBuf,
+}
+
+impl PauseState {
+    pub fn new(screenshot_directory: &Utf8Path) -> Self {
+        Self {
+            path: screenshot_directory.join(PAUSE_FILE_NAME),
+        }
+    }
+
+    /// Pauses recording, for `duration` or until resumed.
+    pub async fn pause(&self, duration: Option<Duration>) -> Result<Pause> {
+        let pause = Pause {
+            until: duration.map(|duration| OffsetDateTime::now_utc() + duration),
+        };
+        let json = serde_json::to_vec(&pause)?;
+        tokio::fs::write(&self.path, json)
+            .await
+            .wrap_err_with(|| format!("unable to write {}", self.path))?;
+        Ok(pause)
+    }
+
+    pub async fn resume(&self) -> Result<()> {
+        match tokio::fs::remove_file(&self.path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).wrap_err_with(|| format!("unable to remove {}", self.path)),
+        }
+    }
+
+    /// The current pause, if recording is paused. Expired pauses are removed.
+    pub async fn current(&self) -> Result<Option<Pause>> {
+        let json = match tokio::fs::read(&self.path).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).wrap_err_with(|| format!("unable to read {}", self.path)),
+        };
+        let pause: Pause = serde_json::from_slice(&json)
+            .wrap_err_with(|| format!("unable to parse {}", self.path))?;
+
+        if pause.is_expired() {
+            self.resume().await?;
+            return Ok(None);
+        }
+        Ok(Some(pause))
+    }
+}
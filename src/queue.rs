@@ -1,15 +1,43 @@
+use std::sync::Arc;
 use std::time::Duration;
 
 use age::secrecy::SecretString;
 use color_eyre::Result;
-use tokio::sync::mpsc;
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinSet;
 use tokio::time;
-use tracing::{debug, error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
 use crate::configuration::{Configuration, ProcessingType};
-use crate::database::{Database, Screenshot};
+use crate::database::{Database, Job, JobStatus, Screenshot};
 use crate::health::SystemHealth;
-use crate::image_processing::{llm, ocr};
+use crate::image_processing::embeddings::{self, TextEmbedder};
+use crate::image_processing::llm::{self, LlmBackend};
+use crate::image_processing::ocr::TextRecognizer;
+
+/// Delay before the first retry of a failed job, doubled for every further attempt.
+const BASE_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Upper bound for the delay between retries.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Screenshots that were saved but whose jobs weren't created yet. The queue creates jobs as
+/// soon as screenshots arrive, so this only needs to cover short delays, the backlog itself is
+/// kept in the database.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// How long jobs that are in progress may take to finish on shutdown. Jobs that take longer
+/// are interrupted and run again on the next start.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+fn retry_backoff(attempts: i64) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    BASE_RETRY_BACKOFF
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_RETRY_BACKOFF)
+}
 
 #[derive(Debug)]
 pub struct WorkItem {
@@ -17,32 +45,161 @@ pub struct WorkItem {
 }
 
 pub struct WorkQueue {
-    rx: mpsc::UnboundedReceiver<WorkItem>,
-    tx: mpsc::UnboundedSender<WorkItem>,
+    rx: mpsc::Receiver<WorkItem>,
+    tx: mpsc::Sender<WorkItem>,
+    worker: Arc<Worker>,
+}
+
+/// State shared by all workers of the queue.
+struct Worker {
     database: Database,
     system_health: SystemHealth,
     passphrase: SecretString,
     configuration: Configuration,
+    embedder: Option<Arc<TextEmbedder>>,
+    text_recognizer: Option<Arc<TextRecognizer>>,
+    llm: Option<Box<dyn LlmBackend>>,
+    /// Notified whenever jobs for a new screenshot were created, or a job finished or failed.
+    new_jobs: Notify,
 }
 
 impl WorkQueue {
-    pub fn new(database: Database, passphrase: SecretString, configuration: Configuration) -> Self {
-        let (tx, rx) = mpsc::unbounded_channel();
+    pub fn new(
+        database: Database,
+        passphrase: SecretString,
+        configuration: Configuration,
+    ) -> Result<Self> {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        // models are loaded once up front, so missing models are reported at startup
+        // instead of failing every job
+        let processing = &configuration.processing;
+        let embedder = if processing.contains(&ProcessingType::Embeddings) {
+            Some(Arc::new(configuration.load_embedder()?))
+        } else {
+            None
+        };
+        let text_recognizer = if processing.contains(&ProcessingType::Ocr) {
+            Some(Arc::new(configuration.load_text_recognizer()?))
+        } else {
+            None
+        };
+        let llm = if processing.contains(&ProcessingType::Llm) {
+            Some(llm::backend(&configuration.llm))
+        } else {
+            None
+        };
 
-        Self {
+        Ok(Self {
             rx,
             tx,
-            database,
-            system_health: SystemHealth::new(),
-            passphrase,
-            configuration,
-        }
+            worker: Arc::new(Worker {
+                database,
+                system_health: SystemHealth::new(),
+                passphrase,
+                configuration,
+                embedder,
+                text_recognizer,
+                llm,
+                new_jobs: Notify::new(),
+            }),
+        })
     }
 
-    pub fn sender(&self) -> mpsc::UnboundedSender<WorkItem> {
+    /// Sender for new screenshots. Their jobs are stored in the database right away, so
+    /// sending only waits while that is slow.
+    pub fn sender(&self) -> mpsc::Sender<WorkItem> {
         self.tx.clone()
     }
 
+    /// Processes screenshots until `shutdown` is cancelled. Jobs in progress get
+    /// [`SHUTDOWN_GRACE_PERIOD`] to finish, interrupted jobs are picked up again on the next
+    /// start.
+    pub async fn start(&mut self, shutdown: CancellationToken) -> Result<()> {
+        info!("waiting a bit before starting work queue");
+        tokio::select! {
+            _ = time::sleep(Duration::from_secs_f64(2.5)) => {}
+            _ = shutdown.cancelled() => return self.create_remaining_jobs().await,
+        }
+        info!("starting work queue");
+
+        let database = &self.worker.database;
+        let configuration = &self.worker.configuration;
+        let interrupted = database.reset_running_jobs().await?;
+        info!("resuming {interrupted} interrupted jobs");
+
+        // screenshots from before jobs existed, or that were saved but not queued before the
+        // recorder stopped. Screenshots that were already processed don't get jobs for newly
+        // configured processing types.
+        let pending = database.find_pending().await?;
+        for screenshot in pending {
+            database
+                .create_jobs(screenshot.id, &configuration.processing)
+                .await?;
+        }
+
+        let mut workers = JoinSet::new();
+        for &processing_type in &configuration.processing {
+            let count = configuration.workers(processing_type);
+            info!("starting {count} {processing_type:?} workers");
+            for _ in 0..count {
+                workers.spawn(self.worker.clone().run(processing_type, shutdown.clone()));
+            }
+        }
+
+        loop {
+            tokio::select! {
+                Some(WorkItem { screenshot }) = self.rx.recv() => {
+                    // screenshots without jobs get them on the next start
+                    if let Err(e) = database
+                        .create_jobs(screenshot.id, &configuration.processing)
+                        .await
+                    {
+                        error!("unable to create jobs for screenshot {}: {e:#}", screenshot.id);
+                    }
+                    self.worker.new_jobs.notify_waiters();
+                }
+                Some(result) = workers.join_next() => {
+                    // workers keep going after errors, they only stop if they panic
+                    result?;
+                }
+                _ = shutdown.cancelled() => break,
+            }
+        }
+
+        info!("stopping work queue");
+        let finished = time::timeout(SHUTDOWN_GRACE_PERIOD, async {
+            while let Some(result) = workers.join_next().await {
+                if let Err(e) = result {
+                    error!("worker failed while stopping: {e}");
+                }
+            }
+        })
+        .await;
+        if finished.is_err() {
+            warn!("jobs didn't finish in time, interrupting them");
+            workers.shutdown().await;
+            database.reset_running_jobs().await?;
+        }
+
+        self.create_remaining_jobs().await?;
+        info!("work queue stopped");
+        Ok(())
+    }
+
+    /// Creates the jobs of screenshots that were saved but not queued yet, anything that
+    /// arrives later gets its jobs on the next start.
+    async fn create_remaining_jobs(&mut self) -> Result<()> {
+        while let Ok(WorkItem { screenshot }) = self.rx.try_recv() {
+            self.worker
+                .database
+                .create_jobs(screenshot.id, &self.worker.configuration.processing)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+impl Worker {
     async fn is_available_for_work(&self) -> bool {
         let load_below_thresholds = self.system_health.load_below_threshold().await;
         info!("system load below thresholds: {load_below_thresholds}");
@@ -50,71 +207,171 @@ impl WorkQueue {
     }
 
     async fn process_llm(&self, screenshot: &Screenshot) -> Result<()> {
-        let result = llm::generate_description(screenshot, &self.passphrase).await?;
-        debug!("llm result: {result}");
+        let Some(backend) = &self.llm else {
+            return Ok(());
+        };
+
+        let description = llm::generate_description(
+            backend.as_ref(),
+            screenshot,
+            &self.passphrase,
+            &self.configuration.llm,
+        )
+        .await?;
+        debug!("llm result: {description:?}");
         self.database
-            .update_description(screenshot.id, &result)
+            .update_description(screenshot.id, &description)
             .await?;
 
         Ok(())
     }
 
     async fn process_ocr(&self, screenshot: &Screenshot) -> Result<()> {
-        let title = ocr::extract_text(screenshot, &self.passphrase).await?;
-        debug!("ocr result: {title}");
+        let Some(text_recognizer) = self.text_recognizer.clone() else {
+            return Ok(());
+        };
+
+        let image = screenshot.load_image(&self.passphrase).await?;
+        let result =
+            tokio::task::spawn_blocking(move || text_recognizer.extract_text(&image)).await??;
+        debug!("ocr result: {}", result.text);
         self.database
-            .update_text_content(screenshot.id, &title)
+            .update_text_content(screenshot.id, &result.text, &result.blocks)
             .await?;
 
         Ok(())
     }
 
     async fn process_embeddings(&self, screenshot: &Screenshot) -> Result<()> {
-        Ok(())
-    }
+        let Some(embedder) = self.embedder.clone() else {
+            return Ok(());
+        };
 
-    async fn do_work(&self, WorkItem { screenshot }: WorkItem) -> Result<()> {
-        for processing_type in &self.configuration.processing {
-            info!(
-                "processing screenshot {} with {processing_type:?}",
+        // OCR and LLM processing happen after the work item was created, so the stored
+        // row has the text that should be embedded.
+        let screenshot = self.database.find_by_id(screenshot.id).await?;
+        let Some(text) = embeddings::embedding_text(&screenshot) else {
+            warn!(
+                "screenshot {} has no text content or description to embed",
                 screenshot.id
             );
-            match processing_type {
-                ProcessingType::Llm => self.process_llm(&screenshot).await?,
-                ProcessingType::Ocr => self.process_ocr(&screenshot).await?,
-                ProcessingType::Embeddings => self.process_embeddings(&screenshot).await?,
+            return Ok(());
+        };
+
+        let embedding = tokio::task::spawn_blocking(move || embedder.embed(&text)).await??;
+        debug!("embedding has {} dimensions", embedding.len());
+        self.database
+            .upsert_embedding(screenshot.id, embeddings::MODEL_NAME, &embedding)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn process(
+        &self,
+        screenshot: &Screenshot,
+        processing_type: ProcessingType,
+    ) -> Result<()> {
+        match processing_type {
+            ProcessingType::Llm => self.process_llm(screenshot).await,
+            ProcessingType::Ocr => self.process_ocr(screenshot).await,
+            ProcessingType::Embeddings => self.process_embeddings(screenshot).await,
+        }
+    }
+
+    async fn run_job(&self, job: Job) -> Result<()> {
+        info!(
+            "processing screenshot {} with {:?} (attempt {})",
+            job.screenshot_id, job.processing_type, job.attempts
+        );
+        let result = match self.database.find_by_id(job.screenshot_id).await {
+            Ok(screenshot) => self.process(&screenshot, job.processing_type).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => self.database.finish_job(&job).await?,
+            Err(e) => {
+                let next_attempt_at = if job.attempts < i64::from(self.configuration.max_attempts) {
+                    let next_attempt_at = OffsetDateTime::now_utc() + retry_backoff(job.attempts);
+                    error!(
+                        "error processing screenshot {} with {:?}, retrying at {next_attempt_at}: {e:?}",
+                        job.screenshot_id, job.processing_type
+                    );
+                    Some(next_attempt_at)
+                } else {
+                    error!(
+                        "error processing screenshot {} with {:?}, giving up after {} attempts: {e:?}",
+                        job.screenshot_id, job.processing_type, job.attempts
+                    );
+                    None
+                };
+                self.database
+                    .fail_job(&job, &format!("{e:#}"), next_attempt_at)
+                    .await?;
             }
         }
+        // jobs that depend on this one may be ready now
+        self.new_jobs.notify_waiters();
 
         Ok(())
     }
 
-    pub async fn start(&mut self) -> Result<()> {
-        info!("waiting a bit before starting work queue");
-        time::sleep(Duration::from_secs_f64(2.5)).await;
-        info!("starting work queue");
+    /// Processes jobs of one processing type until `shutdown` is cancelled. Errors like a
+    /// locked database are usually temporary, so the worker waits for the work interval and
+    /// carries on instead of stopping the queue.
+    async fn run(self: Arc<Self>, processing_type: ProcessingType, shutdown: CancellationToken) {
+        while !shutdown.is_cancelled() {
+            if let Err(e) = self.work(processing_type, &shutdown).await {
+                error!(
+                    "{processing_type:?} worker failed, retrying in {} seconds: {e:#}",
+                    self.configuration.work_interval.as_secs()
+                );
+                tokio::select! {
+                    _ = time::sleep(self.configuration.work_interval) => {}
+                    _ = shutdown.cancelled() => {}
+                }
+            }
+        }
+    }
 
-        let pending = self.database.find_pending().await?;
-        info!("found {} pending screenshots", pending.len());
-        for screenshot in pending {
-            let item = WorkItem { screenshot };
-            self.tx.send(item)?;
+    /// Processes the next due job, or waits for new jobs or the work interval if there is
+    /// none or the system is busy. A job that was started is finished even if `shutdown` is
+    /// cancelled.
+    async fn work(
+        &self,
+        processing_type: ProcessingType,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        if !self.is_available_for_work().await {
+            tokio::select! {
+                _ = time::sleep(self.configuration.work_interval) => {}
+                _ = shutdown.cancelled() => {}
+            }
+            return Ok(());
         }
 
-        loop {
-            if self.is_available_for_work().await {
-                info!("current queue length: {}", self.rx.len());
-
-                debug!("system is available for work");
-                let next_item = self.rx.try_recv();
-                if let Ok(item) = next_item {
-                    debug!("processing work item {item:?}");
-                    if let Err(e) = self.do_work(item).await {
-                        error!("error processing work item: {e:?}");
-                    }
+        // register for notifications before looking for jobs, so jobs that are created in
+        // between aren't missed
+        let new_jobs = self.new_jobs.notified();
+        tokio::pin!(new_jobs);
+        new_jobs.as_mut().enable();
+
+        match self.database.claim_next_job(processing_type).await? {
+            Some(job) => {
+                debug!("processing job {job:?}");
+                self.run_job(job).await?;
+            }
+            None => {
+                let pending = self.database.count_jobs(JobStatus::Pending).await?;
+                debug!("no due {processing_type:?} jobs, {pending} jobs pending");
+                tokio::select! {
+                    _ = time::timeout(self.configuration.work_interval, new_jobs) => {}
+                    _ = shutdown.cancelled() => {}
                 }
             }
-            time::sleep(self.configuration.work_interval).await;
         }
+
+        Ok(())
     }
 }
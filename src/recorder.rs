@@ -1,197 +1,425 @@
 use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 
 use age::secrecy::SecretString;
-use color_eyre::eyre::{eyre, OptionExt};
 use color_eyre::Result;
-use crabgrab::capturable_content::{CapturableContent, CapturableContentFilter};
-use crabgrab::capture_stream::{
-    CaptureAccessToken, CaptureConfig, CapturePixelFormat, CaptureStream,
-};
-use crabgrab::feature::screenshot;
-use crabgrab::prelude::{BoxedSliceFrameBitmap, FrameBitmap, VideoFrameBitmap};
-use image::{DynamicImage, ImageBuffer, ImageFormat, RgbImage, RgbaImage};
+use image::{DynamicImage, ImageFormat, RgbImage};
 use time::OffsetDateTime;
-use tokio::sync::mpsc;
-use tracing::{info, instrument, trace};
+use tokio::sync::{mpsc, Notify};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, instrument, trace, warn};
+use uuid::Uuid;
 
+use crate::capture::{CaptureSource, CaptureType, CapturedScreenshot};
 use crate::configuration::Configuration;
 use crate::database::{Database, NewScreenshot, Screenshot};
 use crate::encryption::encrypt_file;
+use crate::exclusion::ExclusionRules;
+use crate::health;
 use crate::image_processing::similarity::is_similar;
+use crate::pause::PauseState;
 use crate::queue::WorkItem;
+use crate::retention;
 
-#[derive(Debug, Clone, Copy)]
-enum CaptureType {
-    Screen,
-    ActiveWindow,
+/// How often [`ScreenRecorder::wait_for_processing`] checks whether the queue is done.
+const PROCESSING_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+enum CaptureOutcome {
+    Saved(Screenshot),
+    Excluded,
+    TooSimilar,
+    SourceExhausted,
 }
 
-struct CapturedScreenshot {
-    bitmap: BoxedSliceFrameBitmap,
-    app_name: String,
-    title: String,
+/// The parts of the recorder that can be changed while it runs.
+struct Settings {
+    configuration: Configuration,
+    exclusions: ExclusionRules,
+}
+
+impl Settings {
+    fn new(configuration: Configuration) -> Result<Self> {
+        let exclusions = ExclusionRules::new(&configuration.exclusions)?;
+        Ok(Self {
+            configuration,
+            exclusions,
+        })
+    }
 }
 
 pub struct ScreenRecorder {
-    interval: Duration,
-    sender: mpsc::UnboundedSender<WorkItem>,
+    sender: mpsc::Sender<WorkItem>,
     passphrase: SecretString,
-    access_token: CaptureAccessToken,
+    source: Box<dyn CaptureSource>,
     database: Database,
-    configuration: Configuration,
+    settings: RwLock<Arc<Settings>>,
+    pause: PauseState,
+    /// Wakes the recorder up before the interval has passed.
+    wake: Notify,
+    last_capture: Mutex<Option<OffsetDateTime>>,
+    /// Whether the low disk space warning was logged, so it is only logged once until there
+    /// is enough space again.
+    low_disk_space: AtomicBool,
 }
 
 impl ScreenRecorder {
-    pub async fn new(
+    pub fn new(
         database: Database,
-        interval: Duration,
-        sender: mpsc::UnboundedSender<WorkItem>,
+        source: Box<dyn CaptureSource>,
+        sender: mpsc::Sender<WorkItem>,
         passphrase: SecretString,
         configuration: Configuration,
     ) -> Result<Self> {
-        let access_token = CaptureStream::test_access(false);
-        let access_token = match access_token {
-            Some(t) => t,
-            None => CaptureStream::request_access(false)
-                .await
-                .ok_or_eyre("unable to get capture permission")?,
-        };
-
+        let pause = PauseState::new(&configuration.screenshot_directory);
         Ok(Self {
             database,
-            interval,
             sender,
             passphrase,
-            access_token,
-            configuration,
+            source,
+            settings: RwLock::new(Arc::new(Settings::new(configuration)?)),
+            pause,
+            wake: Notify::new(),
+            last_capture: Mutex::new(None),
+            low_disk_space: AtomicBool::new(false),
         })
     }
 
-    #[instrument(skip(self))]
-    async fn capture(&self, capture_type: CaptureType) -> Result<CapturedScreenshot> {
-        let filter = CapturableContentFilter::EVERYTHING_NORMAL;
-        let content = CapturableContent::new(filter).await?;
-        // supported by both windows and macos
-        let format = CapturePixelFormat::Bgra8888;
+    fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
 
-        let active_window = active_win_pos_rs::get_active_window()
-            .map_err(|_| eyre!("unable to get active window"))?;
-        info!("active window: {:?}", active_window);
+    /// Applies a changed configuration from the next capture on. Processing and the retention
+    /// policy only pick up changes after a restart.
+    pub fn reload(&self, configuration: Configuration) -> Result<()> {
+        let settings = Settings::new(configuration)?;
+        *self.settings.write().unwrap() = Arc::new(settings);
+        self.wake.notify_one();
+        Ok(())
+    }
 
-        let window = content
-            .windows()
-            .find(|w| w.application().pid() == active_window.process_id as i32)
-            .ok_or_eyre("could not find active window")?;
+    /// Takes a screenshot right away instead of waiting for the interval to pass.
+    pub fn capture_now(&self) {
+        self.wake.notify_one();
+    }
 
-        let app_name = window.application().name();
-        let title = window.title();
-        info!("capturing window: {} - {}", app_name, title);
+    pub fn pause_state(&self) -> &PauseState {
+        &self.pause
+    }
 
-        let config = match capture_type {
-            CaptureType::Screen => {
-                CaptureConfig::with_display(content.displays().next().unwrap(), format)
-            }
-            CaptureType::ActiveWindow => CaptureConfig::with_window(window, format)?,
-        };
+    /// Makes the recorder check right away whether it was paused or resumed.
+    pub fn pause_changed(&self) {
+        self.wake.notify_one();
+    }
 
-        let video_frame = screenshot::take_screenshot(self.access_token, config).await?;
-        let bitmap = video_frame.get_bitmap()?;
-        Ok(CapturedScreenshot {
-            bitmap,
-            app_name,
-            title,
-        })
+    /// When the last screenshot was saved since the recorder was started.
+    pub fn last_capture(&self) -> Option<OffsetDateTime> {
+        *self.last_capture.lock().unwrap()
     }
 
-    #[instrument(skip(self, screenshot))]
-    async fn should_save_screenshot(&self, screenshot: &RgbImage) -> Result<bool> {
+    #[instrument(skip(self, screenshot, configuration))]
+    async fn should_save_screenshot(
+        &self,
+        screenshot: &RgbImage,
+        configuration: &Configuration,
+    ) -> Result<bool> {
         let last_screenshot = self.database.find_most_recent_screenshot().await?;
         match last_screenshot {
             Some(last_screenshot) => {
                 let last_image = last_screenshot.load_image(&self.passphrase).await?;
                 let last_image = DynamicImage::from(last_image);
                 let screenshot = DynamicImage::ImageRgb8(screenshot.clone());
-                let is_similar = is_similar(
-                    &last_image,
-                    &screenshot,
-                    self.configuration.similarity_threshold,
-                )?;
+                let is_similar =
+                    is_similar(&last_image, &screenshot, configuration.similarity_threshold)?;
                 Ok(!is_similar)
             }
             None => Ok(true),
         }
     }
 
-    #[instrument(skip(self))]
-    async fn create_screenshot(&self) -> Result<Option<Screenshot>> {
-        let CapturedScreenshot {
-            bitmap,
+    #[instrument(skip(self, settings))]
+    async fn create_screenshot(&self, settings: &Settings) -> Result<CaptureOutcome> {
+        let configuration = &settings.configuration;
+        let Some(CapturedScreenshot {
+            image,
             app_name,
             title,
-        } = self.capture(CaptureType::ActiveWindow).await?;
-        let pixels = match bitmap {
-            FrameBitmap::BgraUnorm8x4(frame) => frame,
-            FrameBitmap::ArgbUnormPacked2101010(_) => unimplemented!(),
-            FrameBitmap::RgbaF16x4(_) => unimplemented!(),
-            FrameBitmap::YCbCr(_) => unimplemented!(),
+            timestamp,
+        }) = self.source.capture(CaptureType::ActiveWindow).await?
+        else {
+            return Ok(CaptureOutcome::SourceExhausted);
         };
 
-        // TODO this is probably inefficient...
-        let data: Vec<_> = pixels
-            .data
-            .iter()
-            .copied()
-            .flat_map(|[b, g, r, a]| [r, g, b, a])
-            .collect();
-
-        let image: RgbaImage =
-            ImageBuffer::from_raw(pixels.width as u32, pixels.height as u32, data)
-                .ok_or_eyre("unable to create image buffer")?;
-        let image = DynamicImage::from(image);
-        let image = image.to_rgb8();
+        // the image is dropped here, before it is encrypted or written anywhere
+        if settings.exclusions.is_excluded(&app_name, &title) {
+            if configuration.exclusions.record_excluded {
+                self.database.insert_excluded(timestamp, &app_name).await?;
+            }
+            return Ok(CaptureOutcome::Excluded);
+        }
 
-        if !self.should_save_screenshot(&image).await? {
-            return Ok(None);
+        if !self.should_save_screenshot(&image, configuration).await? {
+            return Ok(CaptureOutcome::TooSimilar);
         }
 
-        let timestamp = OffsetDateTime::now_utc().unix_timestamp();
-        let path = self
-            .configuration
-            .screenshot_directory
-            .join(format!("{}.png.enc", timestamp));
+        // the UUID keeps names unique when several screenshots are taken in the same second
+        let path = configuration.screenshot_directory.join(format!(
+            "{}-{}.png.enc",
+            timestamp.unix_timestamp(),
+            Uuid::new_v4()
+        ));
         let mut bytes = Cursor::new(vec![]);
         image.write_to(&mut bytes, ImageFormat::Png)?;
         encrypt_file(&path, self.passphrase.clone(), bytes.into_inner()).await?;
+        let image_size = tokio::fs::metadata(&path).await?.len();
         let screenshot = NewScreenshot {
             path: path.to_string(),
-            timestamp: OffsetDateTime::now_utc(),
+            image_size: i64::try_from(image_size)?,
+            timestamp,
             window_title: title,
             application_name: app_name,
         };
 
         let screenshot = self.database.insert(screenshot).await?;
 
-        Ok(Some(screenshot))
+        Ok(CaptureOutcome::Saved(screenshot))
+    }
+
+    /// Deletes the oldest images when over the storage quota, and warns when the disk is
+    /// running full.
+    async fn check_storage(&self, configuration: &Configuration) -> Result<()> {
+        if let Some(max_bytes) = configuration.max_storage_bytes {
+            retention::enforce_quota(&self.database, max_bytes).await?;
+        }
+
+        let directory = &configuration.screenshot_directory;
+        if let Some(free) = health::free_disk_space(directory) {
+            let threshold = configuration.min_free_disk_space_bytes;
+            if free >= threshold {
+                self.low_disk_space.store(false, Ordering::Relaxed);
+            } else if !self.low_disk_space.swap(true, Ordering::Relaxed) {
+                warn!("only {free} bytes of disk space are left for {directory}");
+            }
+        }
+
+        Ok(())
     }
 
-    pub async fn start(&self) -> Result<()> {
+    /// Waits until the queue has processed every screenshot that can be processed right away,
+    /// so a replay ends with its results in the database. Screenshots with jobs that wait for a
+    /// retry are left for the next start.
+    async fn wait_for_processing(
+        &self,
+        settings: &Settings,
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        if settings.configuration.processing.is_empty() {
+            return Ok(());
+        }
+
+        loop {
+            let queued = self.sender.max_capacity() - self.sender.capacity();
+            let unprocessed = self.database.count_unprocessed().await?;
+            if queued == 0 && unprocessed == 0 {
+                return Ok(());
+            }
+            debug!("{queued} screenshots are queued and {unprocessed} are waiting for processing");
+            tokio::select! {
+                _ = tokio::time::sleep(PROCESSING_POLL_INTERVAL) => {}
+                _ = shutdown.cancelled() => return Ok(()),
+            }
+        }
+    }
+
+    /// Waits until the next capture is due. Returns `false` when the recorder should stop.
+    async fn wait(&self, settings: &Settings, shutdown: &CancellationToken) -> bool {
+        let interval = settings.configuration.screenshot_interval;
+        trace!("sleeping for {} seconds", interval.as_secs_f64());
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => true,
+            _ = self.wake.notified() => true,
+            _ = shutdown.cancelled() => false,
+        }
+    }
+
+    /// Records screenshots until `shutdown` is cancelled. Finite sources like
+    /// [`crate::capture::ReplaySource`] are captured without waiting in between. Once they run
+    /// out of screenshots, the recorder waits for them to be processed and cancels `shutdown`
+    /// to stop the rest of the app.
+    pub async fn start(&self, shutdown: CancellationToken) -> Result<()> {
         info!("starting screen recorder");
+        retention::measure_images(&self.database).await?;
+        let directory = self.settings().configuration.screenshot_directory.clone();
+        let started_at = OffsetDateTime::now_utc();
+        if let Err(e) =
+            retention::remove_orphaned_files(&self.database, &directory, started_at).await
+        {
+            error!("unable to remove orphaned files: {e:#}");
+        }
+        let mut paused = false;
         loop {
-            match self.create_screenshot().await {
-                Ok(Some(screenshot)) => {
-                    self.sender.send(WorkItem { screenshot })?;
+            let settings = self.settings();
+            match self.pause.current().await {
+                Ok(Some(pause)) => {
+                    if !paused {
+                        match pause.until {
+                            Some(until) => info!("recording is paused until {until}"),
+                            None => info!("recording is paused until `reminisce resume`"),
+                        }
+                        paused = true;
+                    }
+                    if !self.wait(&settings, &shutdown).await {
+                        break;
+                    }
+                    continue;
                 }
                 Ok(None) => {
+                    if paused {
+                        info!("resuming recording");
+                        paused = false;
+                    }
+                }
+                Err(e) => error!("unable to check whether recording is paused: {e:#}"),
+            }
+
+            match self.create_screenshot(&settings).await {
+                Ok(CaptureOutcome::Saved(screenshot)) => {
+                    *self.last_capture.lock().unwrap() = Some(screenshot.timestamp);
+                    // the queue stops taking screenshots on shutdown, their jobs are
+                    // created on the next start instead
+                    tokio::select! {
+                        result = self.sender.send(WorkItem { screenshot }) => result?,
+                        _ = shutdown.cancelled() => {}
+                    }
+                    if let Err(e) = self.check_storage(&settings.configuration).await {
+                        error!("unable to check storage: {e:#}");
+                    }
+                }
+                Ok(CaptureOutcome::Excluded) => {
+                    info!("active window is excluded, skipping");
+                }
+                Ok(CaptureOutcome::TooSimilar) => {
                     info!("screenshots are too similar, skipping");
                 }
+                Ok(CaptureOutcome::SourceExhausted) => {
+                    info!("capture source is exhausted, waiting for processing to finish");
+                    self.wait_for_processing(&settings, &shutdown).await?;
+                    shutdown.cancel();
+                    break;
+                }
                 Err(e) => {
                     info!("error creating screenshot: {e}");
                 }
             }
 
-            trace!("sleeping for {} seconds", self.interval.as_secs_f64());
-            tokio::time::sleep(self.interval).await;
+            let keep_recording = if self.source.is_live() {
+                self.wait(&settings, &shutdown).await
+            } else {
+                !shutdown.is_cancelled()
+            };
+            if !keep_recording {
+                break;
+            }
+        }
+
+        info!("screen recorder stopped");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::{Utf8Path, Utf8PathBuf};
+    use serde_json::json;
+    use time::macros::datetime;
+
+    use super::*;
+    use crate::capture::ReplaySource;
+    use crate::queue::WorkQueue;
+
+    /// Writes a replay directory with frames of different sizes, so none are skipped as too
+    /// similar.
+    fn write_replay(directory: &Utf8Path) {
+        std::fs::create_dir_all(directory).unwrap();
+        for (index, size) in [32, 40, 48].into_iter().enumerate() {
+            let image = RgbImage::from_pixel(size, size, image::Rgb([index as u8 * 100, 0, 0]));
+            image.save(directory.join(format!("{index}.png"))).unwrap();
+        }
+        let manifest = json!([
+            { "file": "0.png", "applicationName": "Terminal", "windowTitle": "cargo test", "timestamp": "2026-10-17T09:00:00Z" },
+            { "file": "1.png", "applicationName": "Firefox", "windowTitle": "Rust docs", "timestamp": "2026-10-17T09:01:00Z" },
+            { "file": "2.png", "applicationName": "Firefox", "windowTitle": "crates.io" },
+        ]);
+        std::fs::write(
+            directory.join("manifest.json"),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_records_every_frame_and_stops() {
+        let root = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("reminisce-replay-{}", Uuid::new_v4()));
+        let replay_directory = root.join("replay");
+        write_replay(&replay_directory);
+        let configuration = Configuration {
+            screenshot_directory: root.join("screenshots"),
+            database_file_name: root.join("reminisce.sqlite3").into_string(),
+            processing: vec![],
+            min_free_disk_space_bytes: 0,
+            ..Configuration::default()
+        };
+        std::fs::create_dir_all(&configuration.screenshot_directory).unwrap();
+
+        let database = Database::new(&configuration.database_file_name)
+            .await
+            .unwrap();
+        let passphrase = SecretString::from("passphrase".to_string());
+        let mut work_queue =
+            WorkQueue::new(database.clone(), passphrase.clone(), configuration.clone()).unwrap();
+        let recorder = ScreenRecorder::new(
+            database.clone(),
+            Box::new(ReplaySource::new(&replay_directory).unwrap()),
+            work_queue.sender(),
+            passphrase,
+            configuration,
+        )
+        .unwrap();
+
+        let shutdown = CancellationToken::new();
+        let run = async {
+            let (recorded, processed) = tokio::join!(
+                recorder.start(shutdown.clone()),
+                work_queue.start(shutdown.clone())
+            );
+            recorded.unwrap();
+            processed.unwrap();
+        };
+        tokio::time::timeout(Duration::from_secs(120), run)
+            .await
+            .expect("replay didn't stop after the last frame");
+
+        let screenshots = database.find_by_time_range(None, None, None).await.unwrap();
+        let recorded: Vec<_> = screenshots
+            .iter()
+            .map(|s| (s.timestamp, s.application_name.as_str()))
+            .collect();
+        assert_eq!(
+            recorded,
+            [
+                (datetime!(2026-10-17 09:00 UTC), "Terminal"),
+                (datetime!(2026-10-17 09:01 UTC), "Firefox"),
+                (datetime!(2026-10-17 09:01:01 UTC), "Firefox"),
+            ]
+        );
+        for screenshot in &screenshots {
+            assert!(Utf8Path::new(&screenshot.path).is_file());
         }
+
+        database.close().await;
+        let _ = std::fs::remove_dir_all(&root);
     }
 }
@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use time::{Duration, OffsetDateTime};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::configuration::RetentionConfiguration;
+use crate::database::{Database, PruneSummary};
+use crate::encryption;
+
+fn cutoff(days: Option<u32>) -> Option<OffsetDateTime> {
+    days.map(|days| OffsetDateTime::now_utc() - Duration::days(days.into()))
+}
+
+/// Deletes everything that is older than the retention policy allows. With `dry_run`, only
+/// counts what would be deleted.
+pub async fn prune(
+    database: &Database,
+    retention: &RetentionConfiguration,
+    dry_run: bool,
+) -> Result<PruneSummary> {
+    let image_cutoff = cutoff(retention.image_days);
+    let metadata_cutoff = cutoff(retention.metadata_days);
+    if dry_run {
+        return database.count_expired(image_cutoff, metadata_cutoff).await;
+    }
+
+    let summary = database.prune(image_cutoff, metadata_cutoff).await?;
+    if summary.images > 0 || summary.screenshots > 0 {
+        info!(
+            "pruned {} images and {} screenshots",
+            summary.images, summary.screenshots
+        );
+    }
+    Ok(summary)
+}
+
+/// Enforces the retention policy until `shutdown` is cancelled. Does nothing if the policy
+/// keeps everything.
+pub async fn run(
+    database: Database,
+    retention: RetentionConfiguration,
+    shutdown: CancellationToken,
+) {
+    if retention.image_days.is_none() && retention.metadata_days.is_none() {
+        return;
+    }
+
+    loop {
+        // a failed prune is retried on the next run
+        if let Err(e) = prune(&database, &retention, false).await {
+            error!("unable to prune old screenshots: {e:#}");
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(retention.interval) => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+/// Records the size of images that were saved before sizes were tracked, so they count towards
+/// the storage quota.
+pub async fn measure_images(database: &Database) -> Result<()> {
+    for (id, path) in database.find_unmeasured_images().await? {
+        let size = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => i64::try_from(metadata.len())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e).wrap_err_with(|| format!("unable to read size of {path}")),
+        };
+        database.set_image_size(id, size).await?;
+    }
+    Ok(())
+}
+
+/// Deletes the oldest images while the images take up more space than `max_bytes`.
+pub async fn enforce_quota(database: &Database, max_bytes: u64) -> Result<()> {
+    let summary = database
+        .evict_oldest_images(i64::try_from(max_bytes)?)
+        .await?;
+    if summary.images > 0 {
+        info!(
+            "storage quota of {max_bytes} bytes exceeded, deleted the {} oldest images",
+            summary.images
+        );
+    }
+    Ok(())
+}
+
+/// Whether a file name looks like an image the recorder saved, `{timestamp}-{uuid}.png.enc`,
+/// or one it was still writing.
+fn is_image_file_name(file_name: &str) -> bool {
+    let file_name = file_name
+        .strip_suffix(encryption::PARTIAL_FILE_SUFFIX)
+        .unwrap_or(file_name);
+    let Some((timestamp, id)) = file_name
+        .strip_suffix(".png.enc")
+        .and_then(|stem| stem.split_once('-'))
+    else {
+        return false;
+    };
+    timestamp.parse::<i64>().is_ok() && Uuid::try_parse(id).is_ok()
+}
+
+/// Removes images that no screenshot refers to, which are left behind when the recorder was
+/// killed while saving a screenshot, or when shredding a deleted image failed. Only files that
+/// were last modified before `before` are removed, so images that are being saved are kept.
+/// Files are matched by name, so moving the data directory doesn't make images look orphaned.
+/// Returns how many files were removed.
+pub async fn remove_orphaned_files(
+    database: &Database,
+    directory: &Utf8Path,
+    before: OffsetDateTime,
+) -> Result<usize> {
+    let known: HashSet<String> = database
+        .find_image_paths()
+        .await?
+        .iter()
+        .filter_map(|path| Utf8Path::new(path).file_name().map(str::to_string))
+        .collect();
+
+    let mut removed = 0;
+    let mut files = tokio::fs::read_dir(directory).await?;
+    while let Some(file) = files.next_entry().await? {
+        let path = Utf8PathBuf::try_from(file.path())?;
+        let Some(file_name) = path.file_name() else {
+            continue;
+        };
+        if !is_image_file_name(file_name) || known.contains(file_name) {
+            continue;
+        }
+        let metadata = file.metadata().await?;
+        if !metadata.is_file() || OffsetDateTime::from(metadata.modified()?) >= before {
+            continue;
+        }
+
+        warn!("removing orphaned file {path}");
+        encryption::shred_file(&path)
+            .await
+            .wrap_err_with(|| format!("unable to remove {path}"))?;
+        removed += 1;
+    }
+
+    if removed > 0 {
+        info!("removed {removed} orphaned files from {directory}");
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_file_names() {
+        let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        assert!(is_image_file_name(&format!("1760000000-{id}.png.enc")));
+        assert!(is_image_file_name(&format!(
+            "1760000000-{id}.png.enc.partial"
+        )));
+        assert!(!is_image_file_name(".test"));
+        assert!(!is_image_file_name("notes.png.enc"));
+        assert!(!is_image_file_name(&format!("yesterday-{id}.png.enc")));
+        assert!(!is_image_file_name("1760000000-backup.png.enc"));
+    }
+}
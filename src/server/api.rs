@@ -0,0 +1,179 @@
+//! JSON API for scripts. Every request needs the token that `reminisce serve` prints on
+//! startup, sent as `Authorization: Bearer <token>`. Times are RFC 3339 strings.
+//!
+//! - `GET /screenshots?from=&to=&app=` lists screenshots taken in a time range, optionally of
+//!   a single application, oldest first. All parameters are optional.
+//! - `GET /screenshots/{id}` returns a screenshot with its structured description, the lines
+//!   of text OCR found on it and its processing jobs.
+//! - `GET /screenshots/{id}/image` returns the decrypted PNG image.
+//! - `DELETE /screenshots/{id}` deletes a screenshot, its image file and everything derived
+//!   from it. A running recorder does that itself.
+//! - `GET /search?q=&from=&to=&app=&limit=` runs a keyword search, best matches first.
+//! - `GET /stats` returns statistics about the recorded screenshots.
+//!
+//! Errors are returned as plain text with a matching status code.
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use camino::Utf8Path;
+use color_eyre::eyre::bail;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::info;
+
+use super::{AppState, ServerResult};
+use crate::control::{self, ControlRequest, ControlResponse};
+use crate::database::{Job, Screenshot, SearchFilters, SearchResult, Statistics, TextBlock};
+use crate::encryption;
+use crate::image_processing::llm::Description;
+use crate::lock::InstanceLock;
+
+#[derive(Debug, Deserialize)]
+pub struct ScreenshotsQuery {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    from: Option<OffsetDateTime>,
+
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    to: Option<OffsetDateTime>,
+
+    app: Option<String>,
+}
+
+pub async fn list_screenshots(
+    State(state): State<AppState>,
+    Query(query): Query<ScreenshotsQuery>,
+) -> ServerResult<Json<Vec<Screenshot>>> {
+    let screenshots = state
+        .database
+        .find_by_time_range(query.from, query.to, query.app.as_deref())
+        .await?;
+    Ok(Json(screenshots))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScreenshotDetails {
+    #[serde(flatten)]
+    screenshot: Screenshot,
+
+    structured_description: Option<Description>,
+
+    /// Lines of text, without the individual words.
+    text_lines: Vec<TextBlock>,
+
+    jobs: Vec<Job>,
+}
+
+pub async fn get_screenshot(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> ServerResult<Json<ScreenshotDetails>> {
+    let screenshot = state.database.find_by_id(id).await?;
+    let structured_description = state.database.find_description(id).await?;
+    let text_lines = state
+        .database
+        .find_text_blocks(id)
+        .await?
+        .into_iter()
+        .filter(|block| block.word.is_none())
+        .collect();
+    let jobs = state.database.find_jobs(id).await?;
+
+    Ok(Json(ScreenshotDetails {
+        screenshot,
+        structured_description,
+        text_lines,
+        jobs,
+    }))
+}
+
+pub async fn get_image(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> ServerResult<Response> {
+    let Some(path) = state.database.find_image_path(id).await? else {
+        return Ok(super::image_deleted());
+    };
+    // opened before responding, so that errors still result in a matching status code
+    let chunks = encryption::decrypt_file_streaming(&path, &state.passphrase).await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, super::IMAGE_CACHE_CONTROL),
+        ],
+        Body::from_stream(ReceiverStream::new(chunks)),
+    )
+        .into_response())
+}
+
+pub async fn delete_screenshot(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> ServerResult<StatusCode> {
+    // like `reminisce delete`, a running recorder has to delete it itself
+    let deleted = match InstanceLock::try_acquire(&state.screenshot_directory)? {
+        Some(_lock) => state.database.delete(id).await?.is_some(),
+        None => delete_through_recorder(&state.screenshot_directory, id).await?,
+    };
+    if !deleted {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+    info!("deleted screenshot {id}");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Asks the running recorder to delete a screenshot. Returns whether it existed.
+async fn delete_through_recorder(screenshot_directory: &Utf8Path, id: i64) -> Result<bool> {
+    let request = ControlRequest::DeleteScreenshot { id };
+    match control::send(screenshot_directory, &request).await? {
+        Some(ControlResponse::Deleted { screenshots }) => Ok(!screenshots.is_empty()),
+        Some(ControlResponse::Error { message }) => bail!("the recorder failed: {message}"),
+        Some(response) => bail!("unexpected response from the recorder: {response:?}"),
+        None => bail!("the recorder is running, but doesn't answer control requests"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    q: String,
+
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    from: Option<OffsetDateTime>,
+
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    to: Option<OffsetDateTime>,
+
+    app: Option<String>,
+
+    limit: Option<i64>,
+}
+
+pub async fn search(
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> ServerResult<Response> {
+    if query.q.trim().is_empty() {
+        return Ok((StatusCode::BAD_REQUEST, "search query must not be empty").into_response());
+    }
+    let filters = SearchFilters {
+        from: query.from,
+        to: query.to,
+        application_name: query.app,
+        limit: query.limit,
+        ..SearchFilters::default()
+    };
+    let results: Vec<SearchResult> = state.database.search(&query.q, &filters).await?;
+    Ok(Json(results).into_response())
+}
+
+pub async fn stats(State(state): State<AppState>) -> ServerResult<Json<Statistics>> {
+    let statistics = state.database.statistics().await?;
+    Ok(Json(statistics))
+}
@@ -0,0 +1,312 @@
+use std::io::Cursor;
+use std::net::{Ipv4Addr, SocketAddr};
+
+use age::secrecy::SecretString;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::get;
+use axum::Router;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use image::imageops::{self, FilterType};
+use image::ImageFormat;
+use rand::RngCore;
+use serde::Deserialize;
+use time::{Date, Duration, OffsetDateTime, UtcOffset};
+use tracing::{error, info};
+
+use crate::database::{Database, SearchFilters};
+use crate::encryption;
+
+mod api;
+mod pages;
+
+/// File in the screenshot directory that keeps the API token between runs.
+const TOKEN_FILE_NAME: &str = ".api-token";
+
+/// Cookie that lets the browser use the web UI after opening the login link.
+const TOKEN_COOKIE: &str = "reminisce_token";
+
+/// Width of the thumbnails in the timeline and search results, in pixels.
+const THUMBNAIL_WIDTH: u32 = 320;
+
+/// Keeps decrypted images out of the browser cache, where they would outlive deleting the
+/// screenshot.
+pub(crate) const IMAGE_CACHE_CONTROL: &str = "no-store";
+
+#[derive(Clone)]
+pub(crate) struct AppState {
+    database: Database,
+    passphrase: SecretString,
+    token: String,
+    screenshot_directory: Utf8PathBuf,
+}
+
+/// Errors are logged and shown as a plain text response. Screenshots that aren't in the
+/// database or whose image was deleted result in a 404.
+pub(crate) struct ServerError(color_eyre::Report);
+
+impl<E: Into<color_eyre::Report>> From<E> for ServerError {
+    fn from(error: E) -> Self {
+        Self(error.into())
+    }
+}
+
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        if let Some(sqlx::Error::RowNotFound) = self.0.downcast_ref::<sqlx::Error>() {
+            return (StatusCode::NOT_FOUND, "not found").into_response();
+        }
+        // the image might be deleted while the request runs
+        if let Some(e) = self.0.downcast_ref::<std::io::Error>() {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                return image_deleted();
+            }
+        }
+
+        // the error chain can contain paths and SQL, so it only goes to the log
+        error!("request failed: {:#}", self.0);
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal server error").into_response()
+    }
+}
+
+pub(crate) type ServerResult<T> = std::result::Result<T, ServerError>;
+
+/// Reads the API token, or creates one that only the current user can read.
+async fn load_or_create_token(directory: &Utf8Path) -> Result<String> {
+    let path = directory.join(TOKEN_FILE_NAME);
+    if path.exists() {
+        let token = tokio::fs::read_to_string(&path)
+            .await
+            .wrap_err_with(|| format!("unable to read {path}"))?;
+        return Ok(token.trim().to_string());
+    }
+
+    let mut bytes = [0; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(&path)
+        .await
+        .wrap_err_with(|| format!("unable to create {path}"))?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, token.as_bytes()).await?;
+
+    Ok(token)
+}
+
+/// Compares in constant time, so the token can't be guessed from response times.
+fn tokens_match(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn request_token(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if bearer.is_some() {
+        return bearer;
+    }
+
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == TOKEN_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Rejects requests without the token, either as bearer token or as cookie set by `/login`.
+async fn require_token(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    match request_token(request.headers()) {
+        Some(token) if tokens_match(&state.token, token) => next.run(request).await,
+        _ => (StatusCode::UNAUTHORIZED, "missing or wrong token").into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginQuery {
+    token: String,
+}
+
+async fn login(State(state): State<AppState>, Query(query): Query<LoginQuery>) -> Response {
+    if !tokens_match(&state.token, &query.token) {
+        return (StatusCode::UNAUTHORIZED, "wrong token").into_response();
+    }
+
+    let cookie = format!(
+        "{TOKEN_COOKIE}={}; Path=/; HttpOnly; SameSite=Strict",
+        state.token
+    );
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/")).into_response()
+}
+
+/// Serves the web UI and the JSON API on localhost until the process is stopped.
+pub async fn serve(
+    database: Database,
+    passphrase: SecretString,
+    screenshot_directory: &Utf8Path,
+    port: u16,
+) -> Result<()> {
+    let token = load_or_create_token(screenshot_directory).await?;
+    let state = AppState {
+        database,
+        passphrase,
+        token,
+        screenshot_directory: screenshot_directory.to_path_buf(),
+    };
+    let router = Router::new()
+        .route("/", get(timeline))
+        .route("/find", get(search))
+        .route("/view/:id", get(viewer))
+        .route("/view/:id/image", get(image))
+        .route("/view/:id/thumbnail", get(thumbnail))
+        .route("/screenshots", get(api::list_screenshots))
+        .route(
+            "/screenshots/:id",
+            get(api::get_screenshot).delete(api::delete_screenshot),
+        )
+        .route("/screenshots/:id/image", get(api::get_image))
+        .route("/search", get(api::search))
+        .route("/stats", get(api::stats))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .route("/login", get(login))
+        .with_state(state.clone());
+
+    // only ever listen on the loopback interface, the server hands out decrypted screenshots
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    info!("serving on http://{address}");
+    println!(
+        "open http://{address}/login?token={} to browse your screenshots",
+        state.token
+    );
+    println!("use `Authorization: Bearer {}` for the API", state.token);
+    axum::serve(listener, router).await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct TimelineQuery {
+    day: Option<String>,
+}
+
+async fn timeline(
+    State(state): State<AppState>,
+    Query(query): Query<TimelineQuery>,
+) -> ServerResult<Html<String>> {
+    // days start at local midnight, the offset can't always be determined though
+    let now = OffsetDateTime::now_utc();
+    let offset = UtcOffset::local_offset_at(now).unwrap_or(UtcOffset::UTC);
+    let day = match query.day.as_deref().filter(|day| !day.is_empty()) {
+        Some(day) => Date::parse(day, pages::DATE_FORMAT)?,
+        None => now.to_offset(offset).date(),
+    };
+    let start = day.midnight().assume_offset(offset);
+    let end = start + Duration::days(1) - Duration::nanoseconds(1);
+    let screenshots = state
+        .database
+        .find_by_time_range(Some(start), Some(end), None)
+        .await?;
+
+    Ok(pages::timeline(day, &screenshots))
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+async fn search(
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> ServerResult<Html<String>> {
+    let results = if query.q.trim().is_empty() {
+        vec![]
+    } else {
+        state
+            .database
+            .search(&query.q, &SearchFilters::default())
+            .await?
+    };
+
+    Ok(pages::search_results(&query.q, &results))
+}
+
+async fn viewer(State(state): State<AppState>, Path(id): Path<i64>) -> ServerResult<Html<String>> {
+    let screenshot = state.database.find_by_id(id).await?;
+    let description = state.database.find_description(id).await?;
+    let lines: Vec<_> = state
+        .database
+        .find_text_blocks(id)
+        .await?
+        .into_iter()
+        .filter(|block| block.word.is_none())
+        .map(|block| block.text)
+        .collect();
+
+    Ok(pages::viewer(&screenshot, description.as_ref(), &lines))
+}
+
+async fn image(State(state): State<AppState>, Path(id): Path<i64>) -> ServerResult<Response> {
+    let Some(path) = state.database.find_image_path(id).await? else {
+        return Ok(image_deleted());
+    };
+    let bytes = encryption::decrypt_file(&path, &state.passphrase).await?;
+
+    Ok(image_response("image/png", bytes))
+}
+
+async fn thumbnail(State(state): State<AppState>, Path(id): Path<i64>) -> ServerResult<Response> {
+    let Some(path) = state.database.find_image_path(id).await? else {
+        return Ok(image_deleted());
+    };
+    let bytes = encryption::decrypt_file(&path, &state.passphrase).await?;
+    let bytes = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+        let image = image::load_from_memory_with_format(&bytes, ImageFormat::Png)?.into_rgb8();
+        let height = (image.height() * THUMBNAIL_WIDTH / image.width().max(1)).max(1);
+        let thumbnail = imageops::resize(&image, THUMBNAIL_WIDTH, height, FilterType::Triangle);
+        let mut bytes = Cursor::new(vec![]);
+        thumbnail.write_to(&mut bytes, ImageFormat::Jpeg)?;
+        Ok(bytes.into_inner())
+    })
+    .await??;
+
+    Ok(image_response("image/jpeg", bytes))
+}
+
+/// Response for screenshots whose image was deleted by the retention policy, while the rest
+/// of the screenshot is kept.
+pub(crate) fn image_deleted() -> Response {
+    (StatusCode::NOT_FOUND, "image was deleted").into_response()
+}
+
+fn image_response(content_type: &'static str, bytes: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, IMAGE_CACHE_CONTROL),
+        ],
+        bytes,
+    )
+        .into_response()
+}
@@ -0,0 +1,238 @@
+use std::fmt::Write;
+
+use axum::response::Html;
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::{Date, Duration};
+
+use crate::database::{ProcessingStatus, Screenshot, SearchResult};
+use crate::image_processing::llm::Description;
+
+pub const DATE_FORMAT: &[FormatItem<'static>] = format_description!("[year]-[month]-[day]");
+
+const TIME_FORMAT: &[FormatItem<'static>] = format_description!("[hour]:[minute]:[second]");
+
+const DATE_TIME_FORMAT: &[FormatItem<'static>] =
+    format_description!("[year]-[month]-[day] [hour]:[minute]:[second] UTC");
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 0; background: #f4f4f5; color: #18181b; }
+header { display: flex; gap: 1rem; align-items: center; padding: 0.75rem 1rem; background: #18181b; }
+header a, header a:visited { color: #fafafa; text-decoration: none; font-weight: bold; }
+header form { margin-left: auto; }
+main { padding: 1rem; }
+nav.days { display: flex; gap: 1rem; align-items: center; margin-bottom: 1rem; }
+input[type=range] { width: 100%; }
+.grid { display: grid; grid-template-columns: repeat(auto-fill, minmax(320px, 1fr)); gap: 1rem; }
+.card { background: white; border-radius: 4px; overflow: hidden; color: inherit; text-decoration: none; }
+.card.selected { outline: 3px solid #2563eb; }
+.card img { width: 100%; display: block; background: #e4e4e7; }
+.card p { margin: 0.5rem; font-size: 0.875rem; overflow: hidden; text-overflow: ellipsis; white-space: nowrap; }
+.viewer img { max-width: 100%; }
+dt { font-weight: bold; margin-top: 0.5rem; }
+pre { white-space: pre-wrap; background: white; padding: 0.5rem; }
+mark { background: #fde047; }
+"#;
+
+/// Moves the selection with the slider above the timeline and scrolls the screenshot into view.
+const SCRUBBER_SCRIPT: &str = r#"
+const scrubber = document.getElementById("scrubber");
+const label = document.getElementById("scrubber-time");
+const cards = document.querySelectorAll(".card");
+scrubber?.addEventListener("input", () => {
+    cards.forEach(card => card.classList.remove("selected"));
+    const card = cards[scrubber.value];
+    card.classList.add("selected");
+    card.scrollIntoView({ block: "center" });
+    label.textContent = card.dataset.time;
+});
+"#;
+
+/// Escapes text for use in HTML content and quoted attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn layout(title: &str, query: &str, body: &str) -> Html<String> {
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title} · reminisce</title>
+<style>{STYLE}</style>
+</head>
+<body>
+<header>
+<a href="/">reminisce</a>
+<form action="/find"><input type="search" name="q" value="{query}" placeholder="Search"></form>
+</header>
+<main>
+{body}
+</main>
+</body>
+</html>"#,
+        title = escape(title),
+        query = escape(query),
+    ))
+}
+
+fn card(screenshot: &Screenshot, caption: &str) -> String {
+    let time = screenshot
+        .timestamp
+        .format(DATE_TIME_FORMAT)
+        .unwrap_or_default();
+    let image = if screenshot.status == ProcessingStatus::Excluded {
+        String::new()
+    } else {
+        format!(
+            r#"<img src="/view/{}/thumbnail" loading="lazy" alt="">"#,
+            screenshot.id
+        )
+    };
+    format!(
+        r#"<a class="card" href="/view/{id}" data-time="{time}">{image}<p>{time} · {app}</p><p>{caption}</p></a>"#,
+        id = screenshot.id,
+        app = escape(&screenshot.application_name),
+    )
+}
+
+pub fn timeline(day: Date, screenshots: &[Screenshot]) -> Html<String> {
+    let day_text = day.format(DATE_FORMAT).unwrap_or_default();
+    let link = |day: Option<Date>, text: &str| match day.and_then(|d| d.format(DATE_FORMAT).ok()) {
+        Some(day) => format!(r#"<a href="/?day={day}">{text}</a>"#),
+        None => String::new(),
+    };
+
+    let mut body = format!(
+        r#"<nav class="days">{previous}<form><input type="date" name="day" value="{day_text}" onchange="this.form.submit()"></form>{next}<span>{count} screenshots</span></nav>"#,
+        previous = link(day.checked_sub(Duration::days(1)), "← previous day"),
+        next = link(day.checked_add(Duration::days(1)), "next day →"),
+        count = screenshots.len(),
+    );
+
+    if screenshots.is_empty() {
+        body.push_str("<p>Nothing was recorded on this day.</p>");
+        return layout(&day_text, "", &body);
+    }
+
+    let first = screenshots[0]
+        .timestamp
+        .format(TIME_FORMAT)
+        .unwrap_or_default();
+    write!(
+        body,
+        r#"<p><input id="scrubber" type="range" min="0" max="{max}" value="0"> <span id="scrubber-time">{first}</span></p><div class="grid">"#,
+        max = screenshots.len() - 1,
+    )
+    .unwrap();
+    for screenshot in screenshots {
+        body.push_str(&card(screenshot, &escape(&screenshot.window_title)));
+    }
+    write!(body, "</div><script>{SCRUBBER_SCRIPT}</script>").unwrap();
+
+    layout(&day_text, "", &body)
+}
+
+/// Turns the `**` markers of a search snippet into `<mark>` elements.
+fn highlight(snippet: &str) -> String {
+    escape(snippet)
+        .split("**")
+        .enumerate()
+        .map(|(i, part)| {
+            if i % 2 == 1 {
+                format!("<mark>{part}</mark>")
+            } else {
+                part.to_string()
+            }
+        })
+        .collect()
+}
+
+pub fn search_results(query: &str, results: &[SearchResult]) -> Html<String> {
+    if query.trim().is_empty() {
+        return layout("Search", query, "<p>Enter something to search for.</p>");
+    }
+    if results.is_empty() {
+        let body = format!("<p>No screenshots found for “{}”.</p>", escape(query));
+        return layout("Search", query, &body);
+    }
+
+    let mut body = String::from(r#"<div class="grid">"#);
+    for result in results {
+        let caption = match &result.snippet {
+            Some(snippet) => highlight(&snippet.replace('\n', " ")),
+            None => escape(&result.screenshot.window_title),
+        };
+        body.push_str(&card(&result.screenshot, &caption));
+    }
+    body.push_str("</div>");
+
+    layout(query, query, &body)
+}
+
+pub fn viewer(
+    screenshot: &Screenshot,
+    description: Option<&Description>,
+    lines: &[String],
+) -> Html<String> {
+    let day = screenshot
+        .timestamp
+        .date()
+        .format(DATE_FORMAT)
+        .unwrap_or_default();
+    let time = screenshot
+        .timestamp
+        .format(DATE_TIME_FORMAT)
+        .unwrap_or_default();
+    let mut body = format!(
+        r#"<div class="viewer"><p><a href="/?day={day}">← back to {day}</a></p>{image}<dl><dt>Taken</dt><dd>{time}</dd><dt>Application</dt><dd>{app}</dd><dt>Window title</dt><dd>{title}</dd><dt>Status</dt><dd>{status:?}</dd>"#,
+        id = screenshot.id,
+        app = escape(&screenshot.application_name),
+        title = escape(&screenshot.window_title),
+        status = screenshot.status,
+        image = if screenshot.status == ProcessingStatus::Excluded {
+            "<p>This window was excluded from recording.</p>".to_string()
+        } else {
+            format!(
+                r#"<a href="/view/{id}/image"><img src="/view/{id}/image" alt=""></a>"#,
+                id = screenshot.id
+            )
+        },
+    );
+
+    let description = description
+        .map(Description::to_text)
+        .or_else(|| screenshot.description.clone());
+    if let Some(description) = description {
+        write!(
+            body,
+            "<dt>Description</dt><dd><pre>{}</pre></dd>",
+            escape(&description)
+        )
+        .unwrap();
+    }
+
+    let text = if lines.is_empty() {
+        screenshot.text_content.clone()
+    } else {
+        Some(lines.join("\n"))
+    };
+    if let Some(text) = text {
+        write!(body, "<dt>Text</dt><dd><pre>{}</pre></dd>", escape(&text)).unwrap();
+    }
+    body.push_str("</dl></div>");
+
+    layout(&format!("Screenshot {}", screenshot.id), "", &body)
+}